@@ -0,0 +1,140 @@
+use tpe::binary::{BinaryReader, BinaryWriter};
+use tpe::ids::{ClientId, TransactionId};
+use tpe::input::InputEvent;
+use tpe::{Ledger, Money, Transaction, TransactionType};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use csv::{ReaderBuilder, Trim};
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const TRANSACTION_COUNT: u32 = 100_000;
+
+/// Builds a mix of deposits and withdrawals spread over many clients
+fn build_transactions() -> Vec<Transaction> {
+    (0..TRANSACTION_COUNT)
+        .map(|id| {
+            let amount = Money(i64::from(id % 1000) * 10000 + 1234);
+
+            let tx_type = if id % 4 == 3 {
+                TransactionType::Withdrawal { amount }
+            } else {
+                TransactionType::Deposit { amount }
+            };
+
+            Transaction {
+                id: TransactionId(id),
+                client_id: ClientId((id % 5000) as u16),
+                tx_type,
+                timestamp: None,
+                invalid: false,
+            }
+        })
+        .collect()
+}
+
+fn build_csv(transactions: &[Transaction]) -> Vec<u8> {
+    let mut csv = String::from("type, client, tx, amount\n");
+
+    for tx in transactions.iter() {
+        let (typ, amount) = match tx.tx_type {
+            TransactionType::Deposit { amount } => ("deposit", amount),
+            TransactionType::Withdrawal { amount } => ("withdrawal", amount),
+            _ => unreachable!("only deposits and withdrawals are generated"),
+        };
+
+        csv.push_str(&format!(
+            "{typ}, {}, {}, {amount}\n",
+            tx.client_id.0, tx.id.0
+        ));
+    }
+
+    csv.into_bytes()
+}
+
+fn build_binary(transactions: &[Transaction]) -> Vec<u8> {
+    let mut wtr = BinaryWriter::new(vec![]).unwrap();
+
+    for tx in transactions.iter() {
+        wtr.write(tx).unwrap();
+    }
+
+    wtr.into_inner().unwrap()
+}
+
+fn replay_csv(input: &[u8]) -> Ledger {
+    let mut ledger = Ledger::new();
+
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(input);
+
+    for event in rdr.deserialize::<InputEvent>() {
+        ledger.append(event.unwrap().parse_transaction().unwrap());
+    }
+
+    ledger
+}
+
+fn replay_binary(input: &[u8]) -> Ledger {
+    let mut ledger = Ledger::new();
+
+    for (_, tx) in BinaryReader::new(input).unwrap() {
+        ledger.append(tx.unwrap());
+    }
+
+    ledger
+}
+
+/// Replays a binary file through the CLI, so reading from the file is measured too.
+/// Withdrawals made before a client's first deposit are rejected, like any other replay.
+fn replay_binary_file(path: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_toy-payments-engine"))
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    assert!(matches!(status.code(), Some(0 | 2)), "{status}");
+}
+
+fn throughput(c: &mut Criterion) {
+    let transactions = build_transactions();
+
+    let csv = build_csv(&transactions);
+    let binary = build_binary(&transactions);
+
+    // Both inputs must replay into the same ledger for the comparison to be fair
+    let csv_ledger = replay_csv(&csv);
+    let binary_ledger = replay_binary(&binary);
+
+    for idx in 0..transactions.len() {
+        assert_eq!(
+            csv_ledger.get_by_index(&idx),
+            binary_ledger.get_by_index(&idx)
+        );
+    }
+
+    let mut group = c.benchmark_group("replay");
+    group.throughput(Throughput::Elements(u64::from(TRANSACTION_COUNT)));
+
+    group.bench_function("csv", |b| b.iter(|| replay_csv(&csv)));
+    group.bench_function("binary", |b| b.iter(|| replay_binary(&binary)));
+
+    let binary_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("throughput.tpeb");
+    fs::write(&binary_file, &binary).unwrap();
+
+    group.bench_function("binary_file", |b| {
+        b.iter(|| replay_binary_file(&binary_file))
+    });
+
+    group.finish();
+
+    fs::remove_file(&binary_file).unwrap();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -1,6 +1,14 @@
-use tpe::Result;
+use crate::camt::{CamtMapping, REFERENCE_FIELDS};
+use crate::dialect::{Column, CsvDialect};
+use crate::reader::InputFormat;
+use crate::writer::OutputFormat;
 
-use std::{env, fs, path::PathBuf};
+use tpe::ids::ClientId;
+use tpe::report::JsonNumbers;
+use tpe::statement::StatementRange;
+use tpe::{AccountPolicy, DebtPolicy, DisputeExpiry, LockPolicy, ReportOrder, Result};
+
+use std::{env, fs, net::SocketAddr, num::NonZeroUsize, path::PathBuf, str::FromStr, thread};
 
 use anyhow::Context;
 
@@ -15,14 +23,818 @@ pub enum InputArgsError {
     FileNotFound(String),
 }
 
-/// Pases the input arguments, requiring the first and only argument to be a valid filepath
-pub fn parse_input_arg() -> Result<PathBuf> {
-    let filename = env::args().nth(1).ok_or_else(|| {
-        InputArgsError::Parse("First argument must be the input file.".to_string())
+/// Options parsed from the command line
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub input: Input,
+    pub input_format: InputFormat,
+    pub csv_dialect: CsvDialect,
+    pub camt_mapping: CamtMapping,
+    pub policy: AccountPolicy,
+
+    /// Account report from an earlier run, with the balances every account starts from
+    pub opening_balances: Option<PathBuf>,
+
+    /// Checkpoint from an earlier run, with the ledger and accounts to carry on from
+    pub resume_from: Option<PathBuf>,
+
+    /// Where to write a checkpoint once the input has been processed, if anywhere
+    pub checkpoint_out: Option<PathBuf>,
+
+    /// Where to write rejected records, if anywhere
+    pub rejects: Option<PathBuf>,
+
+    /// Stop at the first rejected record
+    pub strict: bool,
+
+    /// Where to write the outcome of every row, if anywhere
+    pub outcomes: Option<PathBuf>,
+
+    /// Where to write statistics about the run, if anywhere
+    pub summary: Option<PathBuf>,
+
+    /// Manifest with the control totals expected for the batch
+    pub control: Option<PathBuf>,
+
+    /// Fail unless the batch ends with a trailer
+    pub require_trailer: bool,
+
+    /// How many threads decode and parse records, ahead of the engine
+    pub parse_threads: usize,
+
+    /// Where to write the report, instead of stdout
+    pub output: Option<PathBuf>,
+
+    pub report_order: ReportOrder,
+    pub output_format: OutputFormat,
+
+    /// Whether amounts are strings or numbers in JSON reports
+    pub json_numbers: JsonNumbers,
+}
+
+/// What the application was asked to do
+#[derive(Debug)]
+pub enum Command {
+    /// Process the input, and report on the accounts
+    Process,
+
+    /// Convert the input into the binary format, written to the given path
+    Convert { output: PathBuf },
+
+    /// Process the input, and list a client's ledger entries with running balances
+    Statement {
+        client: ClientId,
+        range: StatementRange,
+    },
+
+    /// Process the input, and compare the accounts against the expected accounts file
+    Reconcile { expected: PathBuf },
+
+    /// Accept transactions over TCP connections on the given address, until stopped
+    Serve { listen: SocketAddr },
+
+    /// Serve the JSON API over HTTP on the given address, until stopped
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    Http { listen: SocketAddr },
+}
+
+/// Where to read transactions from.
+/// `serve` reads from its connections instead, and is given stdin.
+#[derive(Debug)]
+pub enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    /// Names the input, the same way its transaction source does
+    pub fn describe(&self) -> String {
+        match self {
+            Input::Stdin => "stdin".to_string(),
+            Input::File(path) => format!("{path:?}"),
+        }
+    }
+}
+
+/// Parses the input arguments, requiring a valid filepath (or `-` for stdin) as the first
+/// argument, followed by any optional flags.
+/// `convert <input> <output>` converts the input into the binary format instead, and
+/// `statement <input> --client <id>` writes a statement for the client instead of the report, and
+/// `reconcile <input> --expected <path>` writes any differences from the expected accounts, and
+/// `serve --listen <address>` accepts transactions over TCP, and `http --listen <address>` serves
+/// the JSON API, both without an input file.
+pub fn parse_args() -> Result<Args> {
+    parse_args_from(env::args().skip(1))
+}
+
+/// Parses the given arguments, without the program name
+fn parse_args_from(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut args = args.into_iter().peekable();
+
+    let convert = args.next_if_eq("convert").is_some();
+    let statement = !convert && args.next_if_eq("statement").is_some();
+    let reconcile = !convert && !statement && args.next_if_eq("reconcile").is_some();
+    let serve = !convert && !statement && !reconcile && args.next_if_eq("serve").is_some();
+    let http = !convert && !statement && !reconcile && !serve && args.next_if_eq("http").is_some();
+
+    if http && !cfg!(feature = "http") {
+        Err(InputArgsError::Parse(
+            "http requires building with --features http".to_string(),
+        ))?;
+    }
+
+    let filename = if serve || http {
+        "-".to_string()
+    } else {
+        args.next().ok_or_else(|| {
+            InputArgsError::Parse(
+                "First argument must be the input file, or - for stdin.".to_string(),
+            )
+        })?
+    };
+
+    let convert_output = if convert {
+        let output = args
+            .next()
+            .ok_or_else(|| InputArgsError::Parse("convert requires an output file".to_string()))?;
+
+        Some(PathBuf::from(output))
+    } else {
+        None
+    };
+
+    let input = if filename == "-" {
+        Input::Stdin
+    } else {
+        let path = fs::canonicalize(filename.clone())
+            .with_context(|| InputArgsError::FileNotFound(filename))?;
+
+        Input::File(path)
+    };
+
+    let mut input_format = None;
+    let mut csv_dialect = CsvDialect::default();
+    let mut camt_mapping = CamtMapping::default();
+    let mut policy = AccountPolicy::default();
+    let mut dispute_expiry = None;
+    let mut opening_balances = None;
+    let mut resume_from = None;
+    let mut checkpoint_out = None;
+    let mut rejects = None;
+    let mut strict = false;
+    let mut outcomes = None;
+    let mut summary = None;
+    let mut control = None;
+    let mut require_trailer = false;
+    let mut parse_threads = None;
+    let mut output = None;
+    let mut report_order = None;
+    let mut output_format = None;
+    let mut json_numbers = None;
+    let mut statement_client = None;
+    let mut statement_range = StatementRange::default();
+    let mut expected = None;
+    let mut listen = None;
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--input-format" => {
+                input_format = match expect_value(&flag, args.next())?.as_str() {
+                    "csv" => Some(InputFormat::Csv),
+                    "jsonl" => Some(InputFormat::JsonLines),
+                    "camt053" => Some(InputFormat::Camt053),
+                    "binary" => Some(InputFormat::Binary),
+                    other => Err(InputArgsError::Parse(format!(
+                        "{flag} must be one of: csv, jsonl, camt053, binary. Found: {other}"
+                    )))?,
+                };
+            }
+            "--dispute-window-txs" => {
+                policy.dispute_window.max_ledger_distance = Some(parse_value(&flag, args.next())?);
+            }
+            "--dispute-window-secs" => {
+                policy.dispute_window.max_age_secs = Some(parse_value(&flag, args.next())?);
+            }
+            "--opening-balances" => {
+                opening_balances = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--resume-from" => {
+                resume_from = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--checkpoint-out" => {
+                checkpoint_out = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--rejects" => {
+                rejects = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--outcomes" => {
+                outcomes = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--summary" => {
+                summary = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--strict" => {
+                strict = true;
+            }
+            "--control" => {
+                control = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--require-trailer" => {
+                require_trailer = true;
+            }
+            "--parse-threads" => {
+                parse_threads = Some(parse_value::<NonZeroUsize>(&flag, args.next())?.get());
+            }
+            "--output" => {
+                output = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--sort-by" => {
+                report_order = match expect_value(&flag, args.next())?.as_str() {
+                    "client" => Some(ReportOrder::Client),
+                    "available" => Some(ReportOrder::Available),
+                    "held" => Some(ReportOrder::Held),
+                    "total" => Some(ReportOrder::Total),
+                    "locked" => Some(ReportOrder::Locked),
+                    other => Err(InputArgsError::Parse(format!(
+                        "{flag} must be one of: client, available, held, total, locked. Found: {other}"
+                    )))?,
+                };
+            }
+            "--output-format" => {
+                output_format = match expect_value(&flag, args.next())?.as_str() {
+                    "csv" => Some(OutputFormat::Csv),
+                    "json" => Some(OutputFormat::Json),
+                    "jsonl" => Some(OutputFormat::JsonLines),
+                    "markdown" => Some(OutputFormat::Markdown),
+                    other => Err(InputArgsError::Parse(format!(
+                        "{flag} must be one of: csv, json, jsonl, markdown. Found: {other}"
+                    )))?,
+                };
+            }
+            "--json-numbers" => {
+                json_numbers = match expect_value(&flag, args.next())?.as_str() {
+                    "string" => Some(JsonNumbers::Strings),
+                    "number" => Some(JsonNumbers::Numbers),
+                    other => Err(InputArgsError::Parse(format!(
+                        "{flag} must be one of: string, number. Found: {other}"
+                    )))?,
+                };
+            }
+            "--listen" => {
+                listen = Some(parse_value::<SocketAddr>(&flag, args.next())?);
+            }
+            "--expected" => {
+                expected = Some(PathBuf::from(expect_value(&flag, args.next())?));
+            }
+            "--client" => {
+                statement_client = Some(ClientId(parse_value(&flag, args.next())?));
+            }
+            "--from-index" => {
+                statement_range.from_idx = Some(parse_value(&flag, args.next())?);
+            }
+            "--to-index" => {
+                statement_range.to_idx = Some(parse_value(&flag, args.next())?);
+            }
+            "--from-time" => {
+                statement_range.from_time = Some(parse_value(&flag, args.next())?);
+            }
+            "--to-time" => {
+                statement_range.to_time = Some(parse_value(&flag, args.next())?);
+            }
+            "--camt-client-ref" => {
+                camt_mapping.client_ref = parse_reference_field(&flag, args.next())?;
+            }
+            "--camt-tx-ref" => {
+                camt_mapping.tx_ref = parse_reference_field(&flag, args.next())?;
+            }
+            "--camt-currency" => {
+                camt_mapping.currency = Some(expect_value(&flag, args.next())?.to_uppercase());
+            }
+            "--delimiter" => {
+                csv_dialect.delimiter = parse_byte(&flag, args.next())?;
+            }
+            "--quote" => {
+                csv_dialect.quote = parse_byte(&flag, args.next())?;
+            }
+            "--no-quoting" => {
+                csv_dialect.quoting = false;
+            }
+            "--no-headers" => {
+                csv_dialect.has_headers = false;
+            }
+            "--column" => {
+                let (field, column) = parse_pair(&flag, args.next())?;
+
+                let column = match column.parse() {
+                    Ok(position) => Column::Position(position),
+                    Err(_) => Column::Name(column),
+                };
+
+                csv_dialect.columns.insert(field, column);
+            }
+            "--type-alias" => {
+                let (alias, typ) = parse_pair(&flag, args.next())?;
+                csv_dialect.type_aliases.insert(alias, typ);
+            }
+            "--lock-after-chargebacks" => {
+                policy.lock = Some(LockPolicy::AfterChargebacks(parse_value(
+                    &flag,
+                    args.next(),
+                )?));
+            }
+            "--lock-chargeback-ratio" => {
+                policy.lock = Some(LockPolicy::ChargebackRatio {
+                    percent: parse_value(&flag, args.next())?,
+                });
+            }
+            "--never-lock" => {
+                policy.lock = Some(LockPolicy::Never);
+            }
+            "--track-debt" => {
+                policy.debt = DebtPolicy::Track;
+            }
+            "--max-redisputes" => {
+                policy.max_redisputes = Some(parse_value(&flag, args.next())?);
+            }
+            "--dispute-expiry" => {
+                dispute_expiry = match expect_value(&flag, args.next())?.as_str() {
+                    "resolve" => Some(DisputeExpiry::Resolve),
+                    "chargeback" => Some(DisputeExpiry::ChargeBack),
+                    other => Err(InputArgsError::Parse(format!(
+                        "{flag} must be one of: resolve, chargeback. Found: {other}"
+                    )))?,
+                };
+            }
+            _ => Err(InputArgsError::Parse(format!("Unknown argument: {flag}")))?,
+        }
+    }
+
+    // Kept apart until now, so giving the default expiry still counts as using the flag
+    if let Some(on_expiry) = dispute_expiry {
+        policy.dispute_window.on_expiry = on_expiry;
+    }
+
+    if opening_balances.is_some() && resume_from.is_some() {
+        Err(InputArgsError::Parse(
+            "--opening-balances can't be used with --resume-from, which already has the balances"
+                .to_string(),
+        ))?;
+    }
+
+    // Only the account report can be sorted, or written in another format
+    let report_flags = [
+        ("--sort-by", report_order.is_some()),
+        ("--output-format", output_format.is_some()),
+        ("--json-numbers", json_numbers.is_some()),
+    ];
+
+    // Only applying transactions uses the account policy
+    let policy_flags = [
+        (
+            "--dispute-window-txs",
+            policy.dispute_window.max_ledger_distance.is_some(),
+        ),
+        (
+            "--dispute-window-secs",
+            policy.dispute_window.max_age_secs.is_some(),
+        ),
+        ("--dispute-expiry", dispute_expiry.is_some()),
+        ("--max-redisputes", policy.max_redisputes.is_some()),
+        ("--track-debt", policy.debt == DebtPolicy::Track),
+        (
+            "--lock-after-chargebacks",
+            matches!(policy.lock, Some(LockPolicy::AfterChargebacks(_))),
+        ),
+        (
+            "--lock-chargeback-ratio",
+            matches!(policy.lock, Some(LockPolicy::ChargebackRatio { .. })),
+        ),
+        ("--never-lock", policy.lock == Some(LockPolicy::Never)),
+    ];
+
+    let statement_flags = [
+        ("--client", statement_client.is_some()),
+        ("--from-index", statement_range.from_idx.is_some()),
+        ("--to-index", statement_range.to_idx.is_some()),
+        ("--from-time", statement_range.from_time.is_some()),
+        ("--to-time", statement_range.to_time.is_some()),
+    ];
+
+    let command = match convert_output {
+        Some(convert_output) => {
+            let reason = "which only converts the input, without applying it";
+
+            refuse_flags(
+                "convert",
+                reason,
+                &[
+                    ("--opening-balances", opening_balances.is_some()),
+                    ("--resume-from", resume_from.is_some()),
+                    ("--checkpoint-out", checkpoint_out.is_some()),
+                    ("--outcomes", outcomes.is_some()),
+                    ("--summary", summary.is_some()),
+                    ("--control", control.is_some()),
+                    ("--require-trailer", require_trailer),
+                    ("--output", output.is_some()),
+                    ("--listen", listen.is_some()),
+                    ("--expected", expected.is_some()),
+                ],
+            )?;
+            refuse_flags("convert", reason, &report_flags)?;
+            refuse_flags("convert", reason, &statement_flags)?;
+            refuse_flags("convert", reason, &policy_flags)?;
+
+            Command::Convert {
+                output: convert_output,
+            }
+        }
+        None if statement => {
+            refuse_flags("statement", "which is always written as CSV", &report_flags)?;
+            refuse_flags(
+                "statement",
+                "which only lists the client's entries",
+                &[
+                    ("--listen", listen.is_some()),
+                    ("--expected", expected.is_some()),
+                ],
+            )?;
+
+            Command::Statement {
+                client: statement_client.ok_or_else(|| {
+                    InputArgsError::Parse("statement requires --client".to_string())
+                })?,
+                range: statement_range,
+            }
+        }
+        None if reconcile => {
+            refuse_flags("reconcile", "which is always written as CSV", &report_flags)?;
+            refuse_flags(
+                "reconcile",
+                "which only compares every account",
+                &[("--listen", listen.is_some())],
+            )?;
+            refuse_flags(
+                "reconcile",
+                "which only compares every account",
+                &statement_flags,
+            )?;
+
+            Command::Reconcile {
+                expected: expected.ok_or_else(|| {
+                    InputArgsError::Parse("reconcile requires --expected".to_string())
+                })?,
+            }
+        }
+        None if serve => {
+            // Rows are read as CSV lines, and only ever answered with the CSV report
+            refuse_flags(
+                "serve",
+                "which keeps applying rows as they arrive until it's stopped",
+                &[
+                    ("--strict", strict),
+                    ("--input-format", input_format.is_some()),
+                    ("--parse-threads", parse_threads.is_some()),
+                    ("--summary", summary.is_some()),
+                    ("--output", output.is_some()),
+                    ("--checkpoint-out", checkpoint_out.is_some()),
+                    ("--control", control.is_some()),
+                    ("--require-trailer", require_trailer),
+                    ("--output-format", output_format.is_some()),
+                    ("--json-numbers", json_numbers.is_some()),
+                    ("--expected", expected.is_some()),
+                ],
+            )?;
+            refuse_flags(
+                "serve",
+                "which keeps applying rows as they arrive until it's stopped",
+                &statement_flags,
+            )?;
+
+            Command::Serve {
+                listen: listen
+                    .ok_or_else(|| InputArgsError::Parse("serve requires --listen".to_string()))?,
+            }
+        }
+        None if http => {
+            // Rows are only answered over HTTP, with nothing written alongside the responses
+            refuse_flags(
+                "http",
+                "which answers every request in its response",
+                &[
+                    ("--strict", strict),
+                    ("--rejects", rejects.is_some()),
+                    ("--outcomes", outcomes.is_some()),
+                    ("--summary", summary.is_some()),
+                    ("--output", output.is_some()),
+                    ("--checkpoint-out", checkpoint_out.is_some()),
+                    ("--control", control.is_some()),
+                    ("--require-trailer", require_trailer),
+                    ("--input-format", input_format.is_some()),
+                    ("--parse-threads", parse_threads.is_some()),
+                    ("--expected", expected.is_some()),
+                ],
+            )?;
+            refuse_flags(
+                "http",
+                "which answers every request in its response",
+                &statement_flags,
+            )?;
+            refuse_flags("http", "which answers in JSON", &report_flags)?;
+
+            Command::Http {
+                listen: listen
+                    .ok_or_else(|| InputArgsError::Parse("http requires --listen".to_string()))?,
+            }
+        }
+        None if listen.is_some() => Err(InputArgsError::Parse(
+            "--listen is only used by serve and http".to_string(),
+        ))?,
+        None if expected.is_some() => Err(InputArgsError::Parse(
+            "--expected is only used by reconcile".to_string(),
+        ))?,
+        None if statement_client.is_some() || statement_range != StatementRange::default() => {
+            Err(InputArgsError::Parse(
+                "--client and statement ranges are only used by statement".to_string(),
+            ))?
+        }
+        None => Command::Process,
+    };
+
+    // Fall back on the file extension, then CSV
+    let input_format = input_format.unwrap_or_else(|| match &input {
+        Input::File(path) if path.extension().is_some_and(|ext| ext == "jsonl") => {
+            InputFormat::JsonLines
+        }
+        Input::File(path) if path.extension().is_some_and(|ext| ext == "xml") => {
+            InputFormat::Camt053
+        }
+        Input::File(path) if path.extension().is_some_and(|ext| ext == "tpeb") => {
+            InputFormat::Binary
+        }
+        _ => InputFormat::Csv,
+    });
+
+    Ok(Args {
+        command,
+        input,
+        input_format,
+        csv_dialect,
+        camt_mapping,
+        policy,
+        opening_balances,
+        resume_from,
+        checkpoint_out,
+        rejects,
+        strict,
+        outcomes,
+        summary,
+        control,
+        require_trailer,
+        parse_threads: parse_threads.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1)
+        }),
+        output,
+        report_order: report_order.unwrap_or_default(),
+        output_format: output_format.unwrap_or_default(),
+        json_numbers: json_numbers.unwrap_or_default(),
+    })
+}
+
+/// Refuses the first of the flags that was used, since the command would ignore it
+fn refuse_flags(command: &str, reason: &str, flags: &[(&str, bool)]) -> Result {
+    if let Some((flag, _)) = flags.iter().find(|(_, used)| *used) {
+        Err(InputArgsError::Parse(format!(
+            "{flag} can't be used with {command}, {reason}"
+        )))?;
+    }
+
+    Ok(())
+}
+
+fn expect_value(flag: &str, value: Option<String>) -> Result<String> {
+    let value = value.ok_or_else(|| InputArgsError::Parse(format!("Missing value for {flag}")))?;
+
+    Ok(value)
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T> {
+    let value = expect_value(flag, value)?;
+
+    let parsed = value
+        .parse()
+        .map_err(|_| InputArgsError::Parse(format!("Invalid value for {flag}: {value}")))?;
+
+    Ok(parsed)
+}
+
+/// Parses a single byte, accepting `tab` or `\t` for tab separated files
+fn parse_byte(flag: &str, value: Option<String>) -> Result<u8> {
+    let value = expect_value(flag, value)?;
+
+    match value.as_str() {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => Err(InputArgsError::Parse(format!(
+            "{flag} must be a single character. Found: {value}"
+        )))?,
+    }
+}
+
+/// Parses a `key=value` pair
+fn parse_pair(flag: &str, value: Option<String>) -> Result<(String, String)> {
+    let value = expect_value(flag, value)?;
+
+    let (key, value) = value.split_once('=').ok_or_else(|| {
+        InputArgsError::Parse(format!(
+            "{flag} must be in the form key=value. Found: {value}"
+        ))
     })?;
 
-    let path = fs::canonicalize(filename.clone())
-        .with_context(|| InputArgsError::FileNotFound(filename))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parses the name of a camt.053 reference field
+fn parse_reference_field(flag: &str, value: Option<String>) -> Result<String> {
+    let value = expect_value(flag, value)?;
+
+    if !REFERENCE_FIELDS.contains(&value.as_str()) {
+        Err(InputArgsError::Parse(format!(
+            "{flag} must be one of: {}. Found: {value}",
+            REFERENCE_FIELDS.join(", ")
+        )))?;
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        parse_args_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// Asserts the flag is refused by the command, naming the flag
+    fn assert_refused(command: &[&str], flag: &[&str]) {
+        let e = parse(&[command, flag].concat()).unwrap_err();
+
+        assert!(
+            e.to_string()
+                .contains(&format!("{} can't be used with", flag[0])),
+            "{flag:?}: {e}"
+        );
+    }
+
+    #[test]
+    fn convert() {
+        let args = parse(&["convert", "-", "out.tpeb", "--rejects", "rejects.csv"]).unwrap();
+
+        assert!(matches!(args.command, Command::Convert { .. }));
+    }
+
+    #[test]
+    fn refuse_ignored_convert_flags() {
+        for flag in [
+            &["--opening-balances", "accounts.csv"][..],
+            &["--resume-from", "checkpoint.bin"],
+            &["--checkpoint-out", "checkpoint.bin"],
+            &["--outcomes", "outcomes.csv"],
+            &["--summary", "summary.json"],
+            &["--control", "manifest.csv"],
+            &["--require-trailer"],
+            &["--output", "accounts.csv"],
+            &["--listen", "127.0.0.1:0"],
+            &["--expected", "accounts.csv"],
+            &["--sort-by", "total"],
+            &["--output-format", "json"],
+            &["--json-numbers", "number"],
+            &["--client", "1"],
+            &["--from-index", "0"],
+            &["--to-index", "10"],
+            &["--from-time", "0"],
+            &["--to-time", "10"],
+            &["--dispute-window-txs", "10"],
+            &["--dispute-window-secs", "60"],
+            &["--dispute-expiry", "resolve"],
+            &["--max-redisputes", "1"],
+            &["--track-debt"],
+            &["--lock-after-chargebacks", "2"],
+            &["--lock-chargeback-ratio", "50"],
+            &["--never-lock"],
+        ] {
+            assert_refused(&["convert", "-", "out.tpeb"], flag);
+        }
+    }
+
+    #[test]
+    fn refuse_report_flags_for_statements_and_reconciliation() {
+        for flag in [
+            &["--output-format", "json"][..],
+            &["--json-numbers", "number"],
+            &["--sort-by", "total"],
+        ] {
+            assert_refused(&["statement", "-", "--client", "1"], flag);
+            assert_refused(&["reconcile", "-", "--expected", "accounts.csv"], flag);
+        }
+
+        let args = parse(&["statement", "-", "--client", "1", "--output", "out.csv"]).unwrap();
+        assert!(matches!(args.command, Command::Statement { .. }));
+    }
+
+    #[test]
+    fn refuse_other_command_flags_for_statements_and_reconciliation() {
+        assert_refused(
+            &["statement", "-", "--client", "1"],
+            &["--listen", "127.0.0.1:0"],
+        );
+        assert_refused(
+            &["statement", "-", "--client", "1"],
+            &["--expected", "accounts.csv"],
+        );
+
+        for flag in [
+            &["--listen", "127.0.0.1:0"][..],
+            &["--client", "1"],
+            &["--from-index", "0"],
+            &["--to-index", "10"],
+            &["--from-time", "0"],
+            &["--to-time", "10"],
+        ] {
+            assert_refused(&["reconcile", "-", "--expected", "accounts.csv"], flag);
+        }
+    }
+
+    #[test]
+    fn serve() {
+        let args = parse(&[
+            "serve",
+            "--listen",
+            "127.0.0.1:0",
+            "--rejects",
+            "rejects.csv",
+            "--outcomes",
+            "outcomes.csv",
+            "--sort-by",
+            "total",
+        ])
+        .unwrap();
+
+        assert!(matches!(args.command, Command::Serve { .. }));
+    }
+
+    #[test]
+    fn refuse_ignored_serve_flags() {
+        for flag in [
+            &["--strict"][..],
+            &["--input-format", "jsonl"],
+            &["--parse-threads", "2"],
+            &["--summary", "summary.json"],
+            &["--output", "accounts.csv"],
+            &["--checkpoint-out", "checkpoint.bin"],
+            &["--control", "manifest.csv"],
+            &["--require-trailer"],
+            &["--output-format", "json"],
+            &["--json-numbers", "number"],
+            &["--expected", "accounts.csv"],
+            &["--client", "1"],
+            &["--from-index", "0"],
+            &["--to-index", "10"],
+            &["--from-time", "0"],
+            &["--to-time", "10"],
+        ] {
+            assert_refused(&["serve", "--listen", "127.0.0.1:0"], flag);
+        }
+    }
 
-    Ok(path)
+    #[cfg(feature = "http")]
+    #[test]
+    fn refuse_ignored_http_flags() {
+        for flag in [
+            &["--strict"][..],
+            &["--rejects", "rejects.csv"],
+            &["--outcomes", "outcomes.csv"],
+            &["--summary", "summary.json"],
+            &["--output", "accounts.csv"],
+            &["--checkpoint-out", "checkpoint.bin"],
+            &["--control", "manifest.csv"],
+            &["--require-trailer"],
+            &["--input-format", "jsonl"],
+            &["--parse-threads", "2"],
+            &["--output-format", "json"],
+            &["--json-numbers", "number"],
+            &["--sort-by", "total"],
+            &["--expected", "accounts.csv"],
+            &["--client", "1"],
+            &["--from-index", "0"],
+            &["--to-index", "10"],
+            &["--from-time", "0"],
+            &["--to-time", "10"],
+        ] {
+            assert_refused(&["http", "--listen", "127.0.0.1:0"], flag);
+        }
+    }
 }
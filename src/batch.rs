@@ -0,0 +1,111 @@
+use crate::reader::RawInput;
+
+use tpe::{Transaction, TransactionType};
+
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("Batch {0} rolled back: another transaction in the batch failed")]
+    RolledBack(String),
+
+    #[error("Batch {0} is already closed: rows in a batch must be adjacent")]
+    Reused(String),
+}
+
+impl BatchError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            BatchError::RolledBack(_) => "batch_rolled_back",
+            BatchError::Reused(_) => "batch_reused",
+        }
+    }
+}
+
+/// A row waiting to be applied with the rest of its batch
+#[derive(Debug)]
+pub struct BatchRow {
+    pub line: u64,
+    pub raw: RawInput,
+    pub tx: Transaction,
+}
+
+/// Adjacent rows sharing a batch ID, applied together or not at all
+#[derive(Debug)]
+pub struct BatchGroup {
+    pub id: String,
+    pub rows: Vec<BatchRow>,
+
+    /// Set when a row in the batch was rejected before it could be applied
+    pub failed: bool,
+}
+
+/// Collects rows into batch groups as they're read
+#[derive(Debug, Default)]
+pub struct Batches {
+    open: Option<BatchGroup>,
+    closed: HashSet<String>,
+}
+
+impl Batches {
+    /// Closes and returns the open group, if the next row belongs to a different batch
+    pub fn close_unless(&mut self, batch: Option<&str>) -> Option<BatchGroup> {
+        match &self.open {
+            Some(group) if Some(group.id.as_str()) != batch => self.close(),
+            _ => None,
+        }
+    }
+
+    /// Closes and returns the open group, if there is one
+    pub fn close(&mut self) -> Option<BatchGroup> {
+        let group = self.open.take()?;
+        self.closed.insert(group.id.clone());
+
+        Some(group)
+    }
+
+    /// Returns true if a batch has already been closed, so it can't take any more rows
+    pub fn is_closed(&self, batch: &str) -> bool {
+        self.closed.contains(batch)
+    }
+
+    /// Returns true if the open group already holds an identical deposit or withdrawal,
+    /// so a re-delivery within a batch is skipped like one of a committed transaction
+    pub fn is_replay(&self, tx: &Transaction) -> bool {
+        if !matches!(
+            tx.tx_type,
+            TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+        ) {
+            return false;
+        }
+
+        self.open
+            .iter()
+            .flat_map(|group| group.rows.iter())
+            .any(|row| {
+                row.tx.id == tx.id
+                    && row.tx.client_id == tx.client_id
+                    && row.tx.tx_type == tx.tx_type
+            })
+    }
+
+    pub fn add(&mut self, batch: &str, row: BatchRow) {
+        self.find_mut_or_open(batch).rows.push(row);
+    }
+
+    /// Marks the batch as failed, so none of its rows are applied
+    pub fn fail(&mut self, batch: &str) {
+        self.find_mut_or_open(batch).failed = true;
+    }
+
+    fn find_mut_or_open(&mut self, batch: &str) -> &mut BatchGroup {
+        self.open.get_or_insert_with(|| BatchGroup {
+            id: batch.to_string(),
+            rows: vec![],
+            failed: false,
+        })
+    }
+}
@@ -0,0 +1,337 @@
+use crate::reader::{InputRecord, InputRow};
+
+use tpe::input::{InputEvent, InputEventType};
+use tpe::Result;
+
+use std::io::Read;
+
+use roxmltree::{Document, Node};
+
+use thiserror::Error;
+
+/// Reference fields an entry's client and transaction IDs can be read from
+pub const REFERENCE_FIELDS: [&str; 8] = [
+    "AcctSvcrRef",
+    "NtryRef",
+    "MsgId",
+    "PmtInfId",
+    "InstrId",
+    "EndToEndId",
+    "TxId",
+    "Ustrd",
+];
+
+#[derive(Error, Debug)]
+pub enum CamtError {
+    #[error("Invalid camt.053 document: {0}")]
+    InvalidDocument(String),
+
+    #[error("Unmappable camt.053 entry: {0}")]
+    UnmappableEntry(String),
+}
+
+impl CamtError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            CamtError::InvalidDocument(_) => "invalid_document",
+            CamtError::UnmappableEntry(_) => "unmappable_entry",
+        }
+    }
+}
+
+/// Describes where to find the IDs of each camt.053 entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CamtMapping {
+    /// Reference field holding the client ID
+    pub client_ref: String,
+
+    /// Reference field holding the transaction ID
+    pub tx_ref: String,
+
+    /// Currency every entry must be in. Defaults to the statement's own currency.
+    pub currency: Option<String>,
+}
+
+impl Default for CamtMapping {
+    fn default() -> Self {
+        Self {
+            client_ref: "EndToEndId".to_string(),
+            tx_ref: "AcctSvcrRef".to_string(),
+            currency: None,
+        }
+    }
+}
+
+/// Reads the booked entries of an ISO 20022 camt.053 bank statement as input records.
+/// Credits become deposits, and debits become withdrawals. Entries that aren't booked are skipped,
+/// and entries in another currency than the statement are rejected.
+pub fn read_camt_records<R: Read>(
+    mut source: R,
+    mapping: &CamtMapping,
+) -> Result<impl Iterator<Item = InputRecord>> {
+    // The whole document is needed to parse it
+    let mut xml = String::new();
+    source.read_to_string(&mut xml)?;
+
+    let doc = Document::parse(&xml).map_err(|e| CamtError::InvalidDocument(e.to_string()))?;
+
+    let mut records = vec![];
+
+    let mut line = 1;
+    let mut line_pos = 0;
+
+    for entry in doc.descendants().filter(|node| is_element(node, "Ntry")) {
+        let range = entry.range();
+
+        line += xml[line_pos..range.start].matches('\n').count() as u64;
+        line_pos = range.start;
+
+        let status = find_text(&entry, "Sts");
+
+        if status.as_deref() != Some("BOOK") {
+            log::info!("Line {line}: Skipping camt.053 entry with status {status:?}");
+            continue;
+        }
+
+        let currency = mapping
+            .currency
+            .clone()
+            .or_else(|| statement_currency(&entry));
+
+        records.push(InputRecord {
+            line,
+            raw: xml[range].to_string().into(),
+            row: parse_entry(&entry, mapping, currency.as_deref()).map(InputRow::Event),
+        });
+    }
+
+    Ok(records.into_iter())
+}
+
+fn parse_entry(entry: &Node, mapping: &CamtMapping, currency: Option<&str>) -> Result<InputEvent> {
+    let typ = match find_text(entry, "CdtDbtInd").as_deref() {
+        Some("CRDT") => InputEventType::Deposit,
+        Some("DBIT") => InputEventType::Withdrawal,
+        other => Err(CamtError::UnmappableEntry(format!(
+            "credit/debit indicator must be CRDT or DBIT. Found: {other:?}"
+        )))?,
+    };
+
+    if find_text(entry, "RvslInd").as_deref() == Some("true") {
+        Err(CamtError::UnmappableEntry(
+            "reversal entries are not supported".to_string(),
+        ))?;
+    }
+
+    let details = entry
+        .descendants()
+        .filter(|node| is_element(node, "TxDtls"))
+        .count();
+
+    if details > 1 {
+        Err(CamtError::UnmappableEntry(format!(
+            "batched entries are not supported. Found {details} transaction details"
+        )))?;
+    }
+
+    let amount = find_text(entry, "Amt")
+        .ok_or_else(|| CamtError::UnmappableEntry("amount missing".to_string()))?;
+
+    if let Some(currency) = currency {
+        let entry_currency = amount_currency(entry);
+
+        if entry_currency.as_deref() != Some(currency) {
+            Err(CamtError::UnmappableEntry(format!(
+                "amount must be in {currency}. Found: {entry_currency:?}"
+            )))?;
+        }
+    }
+
+    Ok(InputEvent {
+        typ,
+        client: parse_reference(entry, &mapping.client_ref)?,
+        tx: parse_reference(entry, &mapping.tx_ref)?,
+        amount: Some(amount),
+        timestamp: None,
+        batch: None,
+    })
+}
+
+/// Reads an ID from the first element with the given reference field name
+fn parse_reference<T: std::str::FromStr>(entry: &Node, field: &str) -> Result<T> {
+    let value = find_text(entry, field)
+        .ok_or_else(|| CamtError::UnmappableEntry(format!("reference field {field} missing")))?;
+
+    let id = value.parse().map_err(|_| {
+        CamtError::UnmappableEntry(format!(
+            "reference field {field} is not a valid ID. Found: {value}"
+        ))
+    })?;
+
+    Ok(id)
+}
+
+/// Finds the currency of the statement an entry belongs to, from its account.
+/// Falls back to the currency of its first balance or entry, for statements without one.
+fn statement_currency(entry: &Node) -> Option<String> {
+    let statement = entry.ancestors().find(|node| is_element(node, "Stmt"))?;
+
+    let account_currency = statement
+        .children()
+        .find(|child| is_element(child, "Acct"))
+        .and_then(|account| find_text(&account, "Ccy"));
+
+    account_currency.or_else(|| amount_currency(&statement))
+}
+
+/// Reads the `Ccy` attribute of the first amount within the node
+fn amount_currency(node: &Node) -> Option<String> {
+    node.descendants()
+        .find(|child| is_element(child, "Amt"))
+        .and_then(|amount| amount.attribute("Ccy"))
+        .map(|currency| currency.trim().to_string())
+}
+
+/// Finds the trimmed text of the first element with the given name, within the node.
+/// Elements with a `Cd` child, such as `<Sts><Cd>BOOK</Cd></Sts>`, use the code's text.
+fn find_text(node: &Node, name: &str) -> Option<String> {
+    let element = node.descendants().find(|child| is_element(child, name))?;
+
+    let element = element
+        .children()
+        .find(|child| is_element(child, "Cd"))
+        .unwrap_or(element);
+
+    element.text().map(|text| text.trim().to_string())
+}
+
+/// Compares element names without their namespace, since camt.053 versions use different ones
+fn is_element(node: &Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_entry(amount: &str, status: &str, client_ref: &str) -> String {
+        format!(
+            "<Ntry>\
+               {amount}\
+               <CdtDbtInd>CRDT</CdtDbtInd>\
+               <Sts>{status}</Sts>\
+               <AcctSvcrRef>7</AcctSvcrRef>\
+               <NtryDtls><TxDtls><Refs>{client_ref}</Refs></TxDtls></NtryDtls>\
+             </Ntry>"
+        )
+    }
+
+    fn read_entries(account: &str, entries: &[String], mapping: &CamtMapping) -> Vec<InputRecord> {
+        let xml = format!(
+            "<Document xmlns=\"urn:iso:std:iso:20022:tech:xsd:camt.053.001.02\">\
+               <BkToCstmrStmt><Stmt><Acct>{account}</Acct>{}</Stmt></BkToCstmrStmt>\
+             </Document>",
+            entries.concat()
+        );
+
+        read_camt_records(xml.as_bytes(), mapping)
+            .unwrap()
+            .collect()
+    }
+
+    fn assert_unmappable(record: &InputRecord) {
+        let err = record.row.as_ref().unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<CamtError>(),
+            Some(CamtError::UnmappableEntry(_))
+        ));
+    }
+
+    const CAD: &str = "<Amt Ccy=\"CAD\">2.50</Amt>";
+    const CLIENT_1: &str = "<EndToEndId>1</EndToEndId>";
+
+    #[test]
+    fn read_booked_credit() {
+        let records = read_entries(
+            "",
+            &[build_entry(CAD, "BOOK", CLIENT_1)],
+            &CamtMapping::default(),
+        );
+
+        let Ok(InputRow::Event(event)) = &records[0].row else {
+            panic!("expected an event: {:?}", records[0].row);
+        };
+
+        assert!(matches!(event.typ, InputEventType::Deposit));
+        assert_eq!((event.client, event.tx), (1, 7));
+        assert_eq!(event.amount.as_deref(), Some("2.50"));
+    }
+
+    #[test]
+    fn skip_entries_not_booked() {
+        let records = read_entries(
+            "",
+            &[
+                build_entry(CAD, "PDNG", CLIENT_1),
+                build_entry(CAD, "INFO", CLIENT_1),
+            ],
+            &CamtMapping::default(),
+        );
+
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn fail_to_map_missing_reference() {
+        let records = read_entries(
+            "",
+            &[build_entry(CAD, "BOOK", "<TxId>1</TxId>")],
+            &CamtMapping::default(),
+        );
+
+        assert_unmappable(&records[0]);
+    }
+
+    #[test]
+    fn fail_to_map_non_numeric_client() {
+        let records = read_entries(
+            "",
+            &[build_entry(
+                CAD,
+                "BOOK",
+                "<EndToEndId>ACME-CORP</EndToEndId>",
+            )],
+            &CamtMapping::default(),
+        );
+
+        assert_unmappable(&records[0]);
+    }
+
+    #[test]
+    fn fail_to_map_other_currency() {
+        let entries = [
+            build_entry(CAD, "BOOK", CLIENT_1),
+            build_entry("<Amt Ccy=\"USD\">2.50</Amt>", "BOOK", CLIENT_1),
+        ];
+
+        // Without an account currency, the first entry sets the statement's currency
+        let records = read_entries("", &entries, &CamtMapping::default());
+        assert!(records[0].row.is_ok());
+        assert_unmappable(&records[1]);
+
+        let records = read_entries("<Ccy>USD</Ccy>", &entries, &CamtMapping::default());
+        assert_unmappable(&records[0]);
+        assert!(records[1].row.is_ok());
+
+        let mapping = CamtMapping {
+            currency: Some("EUR".to_string()),
+            ..CamtMapping::default()
+        };
+
+        let records = read_entries("<Ccy>CAD</Ccy>", &entries, &mapping);
+        assert_unmappable(&records[0]);
+        assert_unmappable(&records[1]);
+    }
+}
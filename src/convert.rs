@@ -0,0 +1,80 @@
+use crate::args::Args;
+use crate::exit::{self, ExitStatus, ProcessError};
+use crate::pipeline::{self, ParsedRow};
+use crate::reader;
+use crate::rejects::{Reject, RejectStage, Rejects};
+
+use tpe::binary::{self, BinaryError, BinaryWriter};
+use tpe::source::TransactionSource;
+use tpe::Result;
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::Context;
+
+/// Converts the source into the binary format, rejecting any records that can't be parsed,
+/// or that have a timestamp or batch ID the format can't hold.
+/// Records are converted as they are, without being applied to any account.
+pub fn convert_to_binary(
+    source: Box<dyn TransactionSource>,
+    args: &Args,
+    output: &Path,
+    rejects: &mut Rejects,
+) -> Result<ExitStatus> {
+    let unwritable = || exit::unwritable_output(Some(output));
+
+    let file = File::create(output).with_context(unwritable)?;
+    let mut wtr = BinaryWriter::new(BufWriter::new(file)).with_context(unwritable)?;
+    let mut converted = 0;
+
+    let input = source.describe();
+    let records =
+        reader::read_raw_records(source, args).map_err(|e| exit::unavailable_input(e, &input))?;
+
+    for record in pipeline::parse_records(records, args.parse_threads) {
+        let line = record.line;
+
+        let (batch, tx) = match record.row {
+            Ok(ParsedRow::Transaction { batch, tx }) => (batch, tx),
+            Ok(ParsedRow::Trailer(trailer)) => {
+                log::warn!("Line {line}: Control trailers aren't converted: {trailer:?}");
+                continue;
+            }
+            Err(e) if exit::is_unreadable(&e) => {
+                Err(ProcessError::InputUnreadable(line, e.to_string()))?
+            }
+            Err(e) => {
+                rejects.reject(Reject::new(line, &record.raw, RejectStage::Deserialize, &e))?;
+                continue;
+            }
+        };
+
+        // Rejected rather than converted without the fields the format can't hold
+        let tx = tx.and_then(|tx| {
+            if batch.is_some() {
+                Err(BinaryError::Unencodable("batch ID"))?;
+            }
+
+            binary::check_encodable(&tx)?;
+            Ok(tx)
+        });
+
+        match tx {
+            Ok(tx) => {
+                wtr.write(&tx).with_context(unwritable)?;
+                converted += 1;
+            }
+            Err(e) => rejects.reject(Reject::new(line, &record.raw, RejectStage::Parse, &e))?,
+        }
+    }
+
+    wtr.into_inner().with_context(unwritable)?;
+
+    log::info!("Converted {converted} transactions to {output:?}");
+
+    if rejects.count() > 0 {
+        return Ok(ExitStatus::Rejections);
+    }
+
+    Ok(ExitStatus::Success)
+}
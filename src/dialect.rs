@@ -0,0 +1,178 @@
+use tpe::Result;
+
+use std::collections::HashMap;
+
+use csv::{ReaderBuilder, StringRecord};
+
+use thiserror::Error;
+
+/// Field names an InputEvent deserializes from, in their default column order
+pub const FIELDS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "batch"];
+
+#[derive(Error, Debug)]
+pub enum DialectError {
+    #[error("Unknown field in column mapping: {0}")]
+    UnknownField(String),
+
+    #[error("Column not found in headers: {0}")]
+    ColumnNotFound(String),
+}
+
+/// Where to find a field within a CSV record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Position(usize),
+}
+
+/// Describes the shape of a partner's CSV file, and how it maps onto InputEvent fields
+#[derive(Debug, Clone)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub quoting: bool,
+    pub has_headers: bool,
+
+    /// Overrides the column used for an InputEvent field
+    pub columns: HashMap<String, Column>,
+
+    /// Maps type names found in the input to InputEvent type names, ie. `DEP` to `deposit`
+    pub type_aliases: HashMap<String, String>,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            has_headers: true,
+            columns: HashMap::new(),
+            type_aliases: HashMap::new(),
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Builds a reader for the dialect.
+    /// Fields aren't trimmed here, only once they're decoded.
+    /// Records may have any number of fields, missing columns are read as empty.
+    pub fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+
+        builder
+            .flexible(true)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quoting(self.quoting)
+            .has_headers(self.has_headers);
+
+        builder
+    }
+
+    /// Resolves which input column holds each InputEvent field.
+    /// `headers` are the input's own headers, if it has any.
+    pub fn build_mapping(&self, headers: Option<&StringRecord>) -> Result<ColumnMapping> {
+        if let Some(field) = self
+            .columns
+            .keys()
+            .find(|field| !FIELDS.contains(&field.as_str()))
+        {
+            Err(DialectError::UnknownField(field.clone()))?;
+        }
+
+        let mut fields = vec![];
+        let mut indicies = vec![];
+
+        for (default_position, &field) in FIELDS.iter().enumerate() {
+            let index = match (self.columns.get(field), headers) {
+                (Some(Column::Position(position)), _) => Some(*position),
+
+                (Some(Column::Name(name)), Some(headers)) => Some(
+                    find_header(headers, name)
+                        .ok_or_else(|| DialectError::ColumnNotFound(name.clone()))?,
+                ),
+
+                (Some(Column::Name(name)), None) => {
+                    Err(DialectError::ColumnNotFound(name.clone()))?
+                }
+
+                (None, Some(headers)) => find_header(headers, field),
+
+                (None, None) => Some(default_position),
+            };
+
+            if let Some(index) = index {
+                fields.push(field);
+                indicies.push(index);
+            }
+        }
+
+        Ok(ColumnMapping {
+            headers: StringRecord::from(fields),
+            indicies,
+            type_aliases: self.type_aliases.clone(),
+        })
+    }
+}
+
+fn find_header(headers: &StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|header| header == name)
+}
+
+/// Rearranges input records into the fields InputEvent expects
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    headers: StringRecord,
+    indicies: Vec<usize>,
+    type_aliases: HashMap<String, String>,
+}
+
+impl ColumnMapping {
+    /// Headers matching the records built by `map_record`
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Returns the record's other fields in order, if it's a `trailer` control record
+    pub fn trailer_fields<'r>(&self, record: &'r StringRecord) -> Option<Vec<&'r str>> {
+        let type_index = self
+            .headers
+            .iter()
+            .position(|header| header == "type")
+            .map(|position| self.indicies[position])?;
+
+        let typ = record.get(type_index)?;
+        let typ = self.type_aliases.get(typ).map_or(typ, String::as_str);
+
+        if typ != "trailer" {
+            return None;
+        }
+
+        let fields = record
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != type_index)
+            .map(|(_, field)| field)
+            .collect();
+
+        Some(fields)
+    }
+
+    /// Builds a record with only the mapped fields, in the order of `headers`.
+    /// Columns missing from the input record are left empty.
+    pub fn map_record(&self, record: &StringRecord) -> StringRecord {
+        self.headers
+            .iter()
+            .zip(self.indicies.iter())
+            .map(|(header, &index)| {
+                let value = record.get(index).unwrap_or("");
+
+                match header {
+                    "type" => self.type_aliases.get(value).map_or(value, String::as_str),
+                    _ => value,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,125 @@
+use crate::args::InputArgsError;
+use crate::camt::CamtError;
+
+use tpe::binary::BinaryError;
+use tpe::control::ControlTotalsError;
+use tpe::rejection::is_invalid_ledger_state;
+
+use std::{io, path::Path, process::ExitCode};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProcessError {
+    #[error("Input unreadable at line {0}: {1}")]
+    InputUnreadable(u64, String),
+
+    #[error("Couldn't read input from {0}")]
+    InputUnavailable(String),
+
+    #[error("Couldn't write to {0}")]
+    OutputUnwritable(String),
+
+    #[error("Strict mode: stopped at rejected line {0}: {1}")]
+    Rejected(u64, String),
+}
+
+/// Exit codes reported to the calling process, so schedulers can tell failures apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+
+    /// Any failure without a more specific exit code, ie. invalid arguments
+    Failure = 1,
+
+    /// At least one row was rejected. In strict mode, processing stopped at the first one.
+    Rejections = 2,
+
+    /// The input couldn't be opened or read
+    InputUnreadable = 3,
+
+    /// The ledger and account snapshots disagree, so results can't be trusted
+    InvalidLedgerState = 4,
+
+    /// The batch didn't match its control totals, so it wasn't committed
+    ControlTotalsMismatch = 5,
+
+    /// The accounts didn't match the expected accounts they were reconciled against
+    ReconciliationBreaks = 6,
+
+    /// The report, or another output file, couldn't be written
+    OutputUnwritable = 7,
+}
+
+impl ExitStatus {
+    /// Picks the exit status for an error that stopped the application
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        if let Some(e) = error.downcast_ref::<ProcessError>() {
+            return match e {
+                ProcessError::InputUnreadable(..) | ProcessError::InputUnavailable(_) => {
+                    ExitStatus::InputUnreadable
+                }
+                ProcessError::OutputUnwritable(_) => ExitStatus::OutputUnwritable,
+                ProcessError::Rejected(..) => ExitStatus::Rejections,
+            };
+        }
+
+        if error.downcast_ref::<ControlTotalsError>().is_some() {
+            return ExitStatus::ControlTotalsMismatch;
+        }
+
+        if is_invalid_ledger_state(error) {
+            return ExitStatus::InvalidLedgerState;
+        }
+
+        if let Some(InputArgsError::FileNotFound(_)) = error.downcast_ref::<InputArgsError>() {
+            return ExitStatus::InputUnreadable;
+        }
+
+        if let Some(CamtError::InvalidDocument(_)) = error.downcast_ref::<CamtError>() {
+            return ExitStatus::InputUnreadable;
+        }
+
+        if let Some(BinaryError::InvalidHeader(_)) = error.downcast_ref::<BinaryError>() {
+            return ExitStatus::InputUnreadable;
+        }
+
+        ExitStatus::Failure
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// Marks an error from opening or reading the input as unreadable.
+/// Errors about what the input holds are left as they are.
+pub fn unavailable_input(error: anyhow::Error, input: &str) -> anyhow::Error {
+    if is_unreadable(&error) {
+        return error.context(ProcessError::InputUnavailable(input.to_string()));
+    }
+
+    error
+}
+
+/// Describes a failure to write to the given file, or stdout if there isn't one
+pub fn unwritable_output(path: Option<&Path>) -> ProcessError {
+    let output = match path {
+        Some(path) => format!("{path:?}"),
+        None => "stdout".to_string(),
+    };
+
+    ProcessError::OutputUnwritable(output)
+}
+
+/// Returns true if the error came from failing to read the input, rather than from its contents
+pub fn is_unreadable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<io::Error>()
+            || cause
+                .downcast_ref::<csv::Error>()
+                .is_some_and(csv::Error::is_io_error)
+    })
+}
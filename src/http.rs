@@ -0,0 +1,220 @@
+//! Serves the JSON API from `tpe::api` over HTTP.
+//!
+//! Requests are handled one at a time, in the order they're received, so every request sees the
+//! results of the ones before it.
+
+use crate::exit::ExitStatus;
+
+use tpe::api::{Api, ApiResponse};
+use tpe::{AccountSnapshots, Ledger, Result};
+
+use std::{io::Read, net::SocketAddr};
+
+use anyhow::anyhow;
+use tiny_http::{Header, Request, Response, Server};
+
+/// The largest request body that's read, so one request can't exhaust memory
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Listens on the address, and answers requests until the server stops.
+/// The address actually listened on is written to stdout, so port `0` can be used.
+pub fn serve(
+    listen: SocketAddr,
+    ledger: Ledger,
+    snapshots: AccountSnapshots,
+) -> Result<ExitStatus> {
+    let server = Server::http(listen).map_err(|e| anyhow!(e))?;
+
+    if let Some(local_addr) = server.server_addr().to_ip() {
+        println!("Listening on http://{local_addr}");
+    }
+
+    answer_requests(&server, Api::new(ledger, snapshots))?;
+
+    Ok(ExitStatus::Success)
+}
+
+/// Answers every request the server receives, until it stops
+fn answer_requests(server: &Server, mut api: Api) -> Result {
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("header is valid ASCII");
+
+    for mut request in server.incoming_requests() {
+        let response = match read_body(&mut request) {
+            Ok(body) => api.handle(request.method().as_str(), request.url(), &body),
+            Err(response) => response,
+        };
+
+        log::debug!(
+            "{} {} answered with {}",
+            request.method(),
+            request.url(),
+            response.status
+        );
+
+        let mut json = serde_json::to_vec(&response.body)?;
+        json.push(b'\n');
+
+        let response = Response::from_data(json)
+            .with_status_code(response.status)
+            .with_header(content_type.clone());
+
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to send response: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the body of a request, or the response to send if it can't be read
+fn read_body(request: &mut Request) -> std::result::Result<Vec<u8>, ApiResponse> {
+    let too_large = || {
+        ApiResponse::error(
+            413,
+            "body_too_large",
+            format!("Request bodies are limited to {MAX_BODY_BYTES} bytes"),
+        )
+    };
+
+    if request
+        .body_length()
+        .is_some_and(|len| len > MAX_BODY_BYTES)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = vec![];
+
+    // Read one byte past the limit, to tell a body of exactly the limit from a longer one
+    if let Err(e) = request
+        .as_reader()
+        .take(MAX_BODY_BYTES as u64 + 1)
+        .read_to_end(&mut body)
+    {
+        log::warn!("Failed to read request body: {e}");
+        return Err(ApiResponse::error(400, "unreadable_body", e));
+    }
+
+    if body.len() > MAX_BODY_BYTES {
+        return Err(too_large());
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Write, net::TcpStream, thread};
+
+    use serde_json::{json, Value};
+
+    /// Starts a server on any free port, returning its address
+    fn start_server() -> SocketAddr {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+
+        thread::spawn(move || {
+            answer_requests(&server, Api::new(Ledger::new(), AccountSnapshots::new()))
+        });
+
+        addr
+    }
+
+    /// Sends a request over a new connection, returning the status line, headers, and body
+    fn send(addr: SocketAddr, method: &str, path: &str, body: &[u8]) -> (String, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\n\
+             Host: {addr}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+
+        // A body over the limit is answered without being read, so the rest may not be taken
+        let _ = stream.write_all(body);
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..split].to_vec()).unwrap();
+        let body = response[split + 4..].to_vec();
+
+        let (status, headers) = head.split_once("\r\n").unwrap_or((&head, ""));
+
+        (status.to_string(), headers.to_lowercase(), body)
+    }
+
+    fn send_json(addr: SocketAddr, method: &str, path: &str, body: &str) -> (String, Value) {
+        let (status, headers, body) = send(addr, method, path, body.as_bytes());
+
+        assert!(
+            headers.contains("content-type: application/json"),
+            "{headers}"
+        );
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn serve_requests() {
+        let addr = start_server();
+
+        let (status, body) = send_json(
+            addr,
+            "POST",
+            "/transactions",
+            r#"[{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5},
+                {"type": "withdrawal", "client": 1, "tx": 2, "amount": "5.0"}]"#,
+        );
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["outcomes"][0]["status"], "accepted");
+        assert_eq!(body["outcomes"][1]["reason"], "invalid_withdrawal");
+
+        let (status, body) = send_json(addr, "GET", "/accounts/1", "");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            json!({
+                "client": "1",
+                "available": "2.5000",
+                "held": "0.0000",
+                "total": "2.5000",
+                "locked": false,
+            })
+        );
+
+        let (status, body) = send_json(addr, "GET", "/accounts/2", "");
+
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert_eq!(body["error"], "account_not_found");
+    }
+
+    #[test]
+    fn refuse_large_bodies() {
+        let addr = start_server();
+
+        let body = vec![b' '; MAX_BODY_BYTES + 1];
+        let (status, _, response) = send(addr, "POST", "/transactions", &body);
+
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+
+        let response: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["error"], "body_too_large");
+
+        // Nothing was applied, and the server keeps answering
+        let (status, body) = send_json(addr, "GET", "/accounts", "");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, json!([]));
+    }
+}
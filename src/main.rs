@@ -1,91 +1,505 @@
 mod args;
+mod batch;
+mod camt;
 mod config;
+mod convert;
+mod dialect;
+mod exit;
+#[cfg(feature = "http")]
+mod http;
+mod outcomes;
+mod pipeline;
 mod reader;
+mod rejects;
+mod serve;
 mod writer;
 
-use tpe::{input::InputEvent, AccountSnapshots, Ledger, Result};
+use args::{Args, Command};
+use batch::{BatchError, BatchGroup, BatchRow, Batches};
+use exit::{ExitStatus, ProcessError};
+use outcomes::Outcomes;
+use pipeline::ParsedRow;
+use reader::RawInput;
+use rejects::{Reject, RejectStage, Rejects};
 
-fn main() -> Result {
+use tpe::control::{ControlTotals, ControlTotalsError};
+use tpe::outcome::{self, Outcome, OutcomeStatus};
+use tpe::reconcile;
+use tpe::rejection;
+use tpe::source::TransactionSource;
+use tpe::statement::Statement;
+use tpe::summary::Summary;
+use tpe::{AccountSnapshots, GroupRollback, Ledger, Result, Transaction};
+
+use std::process::ExitCode;
+
+use anyhow::Context;
+
+/// How many of the largest balances are listed in the summary
+const LARGEST_BALANCES: usize = 5;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitStatus::from_error(&e).into()
+        }
+    }
+}
+
+fn run() -> Result<ExitStatus> {
     config::configure_app()?;
 
     log::debug!("Application configured. Beginning process...");
 
-    let mut ledger = Ledger::new();
-    let mut snapshots = AccountSnapshots::new();
+    let args = args::parse_args()?;
+    log::debug!("Parsed input args: {args:?}");
+
+    if let Command::Serve { listen } = &args.command {
+        let (ledger, snapshots) = open_engine(&args)?;
+
+        return serve::serve(*listen, &args, ledger, snapshots);
+    }
+
+    #[cfg(feature = "http")]
+    if let Command::Http { listen } = &args.command {
+        let (ledger, snapshots) = open_engine(&args)?;
+
+        return http::serve(*listen, ledger, snapshots);
+    }
+
+    let source = reader::open_source(&args.input)
+        .map_err(|e| exit::unavailable_input(e, &args.input.describe()))?;
+    log::debug!("Reading transactions from {}", source.describe());
+
+    if let Command::Convert { output } = &args.command {
+        let mut rejects = Rejects::new(args.rejects.as_deref(), args.strict)?;
+
+        let converted = convert::convert_to_binary(source, &args, output, &mut rejects);
+        rejects.flush()?;
+
+        return converted;
+    }
+
+    let (mut ledger, mut snapshots) = open_engine(&args)?;
+
+    // Entries before this were carried over from a checkpoint, rather than read this run
+    let resumed_len = ledger.len();
+
+    let manifest = match &args.control {
+        Some(path) => Some(reader::read_manifest(path)?),
+        None => None,
+    };
+
+    let mut rejects = Rejects::new(args.rejects.as_deref(), args.strict)?;
+    let mut outcomes = Outcomes::new(args.outcomes.as_deref())?;
+
+    let processed = process_data(
+        source,
+        &args,
+        &mut ledger,
+        &mut snapshots,
+        &mut rejects,
+        &mut outcomes,
+    );
+
+    // Keep whatever was rejected, even if processing stopped early
+    rejects.flush()?;
+    outcomes.flush()?;
+
+    let stats = processed?;
+
+    // Refused input was never reported, so the outcomes of its rows would be misleading.
+    // Rejects are kept, since they can explain why the totals didn't match.
+    if let Err(e) = check_control_totals(&args, manifest, &stats) {
+        outcomes.discard()?;
+        return Err(e);
+    }
+
+    if let Some(path) = &args.checkpoint_out {
+        log::debug!(
+            "Writing checkpoint of {} ledger entries to {path:?}",
+            ledger.len()
+        );
+        writer::write_checkpoint(path, &ledger, &snapshots)
+            .with_context(|| exit::unwritable_output(Some(path)))?;
+    }
+
+    // Only after the checkpoint, since a later run may hold entries from before the end of this
+    // one's window. Closing disputes against this run's latest timestamp would close them early.
+    log::debug!("Closing disputes with expired windows...");
+    snapshots.expire_disputes(&ledger)?;
 
-    process_data(&mut ledger, &mut snapshots)?;
+    if stats.duplicates > 0 {
+        log::info!(
+            "Skipped {} identical duplicate transactions",
+            stats.duplicates
+        );
+    }
 
     log::debug!("Process complete. Beginning report...");
 
-    report_to_std_out(&snapshots)?;
+    if let Some(path) = &args.summary {
+        let summary = writer::RunSummary {
+            rows_read: stats.totals.record_count,
+            duplicates_skipped: stats.duplicates,
+            rejected: rejects.count_by_stage(),
+            summary: Summary::build(&ledger, &snapshots, resumed_len, LARGEST_BALANCES)?,
+        };
+
+        log::debug!("Writing summary to {path:?}");
+        writer::write_summary(path, &summary)
+            .with_context(|| exit::unwritable_output(Some(path)))?;
+    }
+
+    match &args.command {
+        Command::Statement { client, range } => {
+            log::debug!("Building statement for client {client} over {range:?}");
+            let statement = Statement::build(&ledger, &snapshots, *client, *range)?;
+
+            writer::write_statement(args.output.as_deref(), &statement)
+                .with_context(|| exit::unwritable_output(args.output.as_deref()))?;
+        }
+        Command::Reconcile { expected } => {
+            let expected = reader::read_accounts(expected)?;
+            let actual = snapshots.build_report()?;
+
+            let breaks = reconcile::reconcile(&expected, &actual)?;
+            writer::write_breaks(args.output.as_deref(), &breaks)
+                .with_context(|| exit::unwritable_output(args.output.as_deref()))?;
+
+            if !breaks.is_empty() {
+                log::warn!(
+                    "Reconciliation found {} breaks across {} expected accounts",
+                    breaks.len(),
+                    expected.len()
+                );
+                return Ok(ExitStatus::ReconciliationBreaks);
+            }
+
+            log::info!("Reconciled {} accounts with no breaks", expected.len());
+        }
+        _ => write_report(&args, &snapshots)?,
+    }
+
+    if rejects.count() > 0 {
+        log::warn!("Completed with {} rejected records", rejects.count());
+        return Ok(ExitStatus::Rejections);
+    }
 
     log::debug!("Application finished successfully!");
 
-    Ok(())
+    Ok(ExitStatus::Success)
+}
+
+/// Starts the ledger and accounts, either from a checkpoint, opening balances, or empty
+fn open_engine(args: &Args) -> Result<(Ledger, AccountSnapshots)> {
+    let (ledger, mut snapshots) = match &args.resume_from {
+        Some(path) => {
+            log::debug!("Resuming from checkpoint {path:?}");
+            reader::read_checkpoint(path)?.resume(args.policy)?
+        }
+        None => (Ledger::new(), AccountSnapshots::with_policy(args.policy)),
+    };
+
+    if let Some(path) = &args.opening_balances {
+        let accounts = reader::read_accounts(path)?;
+        log::debug!("Opening {} accounts from {path:?}", accounts.len());
+
+        for account in accounts.iter() {
+            snapshots.open_account(account)?;
+        }
+    }
+
+    Ok((ledger, snapshots))
+}
+
+/// Counts gathered while processing the input
+#[derive(Debug, Default)]
+struct ProcessStats {
+    duplicates: usize,
+
+    /// Totals of the records actually read
+    totals: ControlTotals,
+
+    /// Totals from the trailer, if the input ended with one.
+    /// A trailer that couldn't be parsed is kept as its error, so the batch is still refused.
+    trailer: Option<std::result::Result<ControlTotals, ControlTotalsError>>,
 }
 
-/// Read input file, process, and store results
-fn process_data(ledger: &mut Ledger, snapshots: &mut AccountSnapshots) -> Result {
-    let input_path = args::parse_input_arg()?;
-    log::debug!("Found filepath as input arg: {input_path:?}");
+/// Read transactions from the source, process, and store results.
+/// Stops early if the input can't be read, or the ledger is left in an invalid state.
+fn process_data(
+    source: Box<dyn TransactionSource>,
+    args: &Args,
+    ledger: &mut Ledger,
+    snapshots: &mut AccountSnapshots,
+    rejects: &mut Rejects,
+    outcomes: &mut Outcomes,
+) -> Result<ProcessStats> {
+    let mut stats = ProcessStats::default();
+    let mut batches = Batches::default();
+
+    log::debug!("Deserializing {:?} reader...", args.input_format);
+    let input = source.describe();
+    let records =
+        reader::read_raw_records(source, args).map_err(|e| exit::unavailable_input(e, &input))?;
 
-    let mut rdr = reader::build_csv_reader(input_path)?;
+    for record in pipeline::parse_records(records, args.parse_threads) {
+        log::debug!("Processing parsed record: {record:?}");
+        let line = record.line;
+
+        if stats.trailer.is_some() {
+            Err(ControlTotalsError::RecordAfterTrailer(line))?;
+        }
 
-    log::debug!("Deserializing reader...");
-    for record in rdr.deserialize::<InputEvent>() {
-        log::debug!("Parsing record into InputEvent: {record:?}");
-        let input_event = match record {
-            Ok(input_event) => input_event,
+        let (batch, parsed) = match record.row {
+            Ok(ParsedRow::Transaction { batch, tx }) => {
+                stats.totals.count_record();
+                (batch, tx)
+            }
+            Ok(ParsedRow::Trailer(trailer)) => {
+                log::debug!("Read trailer: {trailer:?}");
+                stats.trailer = Some(Ok(trailer));
+                continue;
+            }
+            Err(e) if e.is::<ControlTotalsError>() => {
+                log::debug!("Read invalid trailer at line {line}: {e}");
+                stats.trailer = Some(Err(e.downcast()?));
+                continue;
+            }
+            Err(e) if exit::is_unreadable(&e) => {
+                Err(ProcessError::InputUnreadable(line, e.to_string()))?
+            }
             Err(e) => {
-                log::warn!("{e}");
+                stats.totals.count_record();
+
+                let reject = Reject::new(line, &record.raw, RejectStage::Deserialize, &e);
+                outcomes.record(line, || Ok(reject.outcome(None)))?;
+                rejects.reject(reject)?;
                 continue;
             }
         };
 
-        log::debug!("Parsing input_event into Transaction: {input_event:?}");
-        let tx = match input_event.parse_transaction() {
+        if let Some(group) = batches.close_unless(batch.as_deref()) {
+            apply_group(group, ledger, snapshots, rejects, outcomes)?;
+        }
+
+        if let Some(batch) = batch.as_deref().filter(|batch| batches.is_closed(batch)) {
+            let e = BatchError::Reused(batch.to_string()).into();
+
+            let reject = Reject::new(line, &record.raw, RejectStage::Parse, &e);
+            outcomes.record(line, || Ok(reject.outcome(parsed.as_ref().ok())))?;
+            rejects.reject(reject)?;
+            continue;
+        }
+
+        let tx = match parsed {
             Ok(tx) => tx,
             Err(e) => {
-                log::warn!("{e}");
+                if let Some(batch) = &batch {
+                    batches.fail(batch);
+                }
+
+                let reject = Reject::new(line, &record.raw, RejectStage::Parse, &e);
+                outcomes.record(line, || Ok(reject.outcome(None)))?;
+                rejects.reject(reject)?;
+                continue;
+            }
+        };
+
+        stats.totals.add_transaction(&tx)?;
+
+        if let Some(batch) = &batch {
+            // Held rows aren't applied yet, so they're checked for replays as they're read,
+            // against the ledger and the rows held before them
+            if ledger.is_replay(&tx) || batches.is_replay(&tx) {
+                log::debug!("Skipping identical duplicate transaction: {tx:?}");
+                stats.duplicates += 1;
+
+                outcomes.record(line, || {
+                    Outcome::skipped(&tx, "duplicate").with_account(snapshots.find(tx.client_id))
+                })?;
                 continue;
             }
+
+            log::debug!("Holding transaction for batch {batch}: {tx:?}");
+            batches.add(
+                batch,
+                BatchRow {
+                    line,
+                    raw: record.raw,
+                    tx,
+                },
+            );
+            continue;
+        }
+
+        let status =
+            apply_transaction(line, &record.raw, tx, ledger, snapshots, rejects, outcomes)?;
+
+        if status == OutcomeStatus::Skipped {
+            stats.duplicates += 1;
+        }
+    }
+
+    if let Some(group) = batches.close() {
+        apply_group(group, ledger, snapshots, rejects, outcomes)?;
+    }
+
+    Ok(stats)
+}
+
+/// Append a single transaction to the ledger and apply it to its account, unless it's a replay.
+/// Returns the status of its outcome.
+/// Fails if the ledger is left in an invalid state, or in strict mode if it's rejected.
+fn apply_transaction(
+    line: u64,
+    raw: &RawInput,
+    tx: Transaction,
+    ledger: &mut Ledger,
+    snapshots: &mut AccountSnapshots,
+    rejects: &mut Rejects,
+    outcomes: &mut Outcomes,
+) -> Result<OutcomeStatus> {
+    let client_id = tx.client_id;
+
+    let outcome = outcome::apply_transaction(ledger, snapshots, tx)?;
+    let status = outcome.status;
+    let reject = Reject::from_outcome(line, raw, &outcome);
+
+    outcomes.record(line, || outcome.with_account(snapshots.find(client_id)))?;
+
+    if let Some(reject) = reject {
+        rejects.reject(reject)?;
+    }
+
+    Ok(status)
+}
+
+/// Append a batch group to the ledger and apply it as one unit.
+/// If any row in the group fails, every row is rejected and invalidated.
+/// Outcomes of the rows all have the balances from after the whole group.
+fn apply_group(
+    group: BatchGroup,
+    ledger: &mut Ledger,
+    snapshots: &mut AccountSnapshots,
+    rejects: &mut Rejects,
+    outcomes: &mut Outcomes,
+) -> Result {
+    log::debug!("Appending batch {} to ledger", group.id);
+    let ledger_indicies: Vec<usize> = group
+        .rows
+        .iter()
+        .map(|row| ledger.append(row.tx.clone()))
+        .collect();
+
+    let (failed_idx, error) = if group.failed {
+        for ledger_idx in ledger_indicies.iter() {
+            ledger.invalidate(ledger_idx);
+        }
+
+        (None, None)
+    } else {
+        log::debug!(
+            "Applying batch {} at indicies: {ledger_indicies:?}",
+            group.id
+        );
+        match snapshots.apply_group(ledger, &ledger_indicies) {
+            Ok(()) => {
+                for (row, ledger_idx) in group.rows.iter().zip(ledger_indicies) {
+                    outcomes.record(row.line, || {
+                        Outcome::accepted(&row.tx)
+                            .at(ledger_idx)
+                            .with_account(snapshots.find(row.tx.client_id))
+                    })?;
+                }
+
+                return Ok(());
+            }
+            Err(e) => {
+                // An invalid ledger state takes priority over rejecting the group
+                if rejection::is_invalid_ledger_state(&e) {
+                    return Err(e);
+                }
+
+                let failed_idx = e.downcast_ref::<GroupRollback>().and_then(|g| g.failed_idx);
+                (failed_idx, Some(e))
+            }
+        }
+    };
+
+    let rolled_back = BatchError::RolledBack(group.id).into();
+
+    for (row, ledger_idx) in group.rows.iter().zip(ledger_indicies) {
+        let e = match &error {
+            Some(e) if failed_idx == Some(ledger_idx) => e,
+            _ => &rolled_back,
         };
 
-        let client_id = tx.client_id;
+        let reject = Reject::new(row.line, &row.raw, RejectStage::Apply, e);
 
-        log::debug!("Appending transaction to ledger: {tx:?}");
-        let ledger_idx = ledger.append(tx);
+        outcomes.record(row.line, || {
+            reject
+                .outcome(Some(&row.tx))
+                .at(ledger_idx)
+                .with_account(snapshots.find(row.tx.client_id))
+        })?;
+        rejects.reject(reject)?;
+    }
 
-        log::debug!("Appended at index: {ledger_idx}");
+    Ok(())
+}
 
-        let snapshot = snapshots.find_mut_or_create(client_id);
+/// Refuse the batch unless what was read matches its manifest and trailer
+fn check_control_totals(
+    args: &Args,
+    manifest: Option<ControlTotals>,
+    stats: &ProcessStats,
+) -> Result {
+    if let Some(manifest) = manifest {
+        log::debug!("Checking {:?} against manifest {manifest:?}", stats.totals);
+        stats.totals.check(&manifest)?;
+    }
 
-        log::debug!("Applying to snapshot: {snapshot:?}");
-        if let Err(e) = snapshot.apply_transactions(ledger) {
-            log::warn!("{e}");
+    match &stats.trailer {
+        Some(Ok(trailer)) => {
+            log::debug!("Checking {:?} against trailer {trailer:?}", stats.totals);
+            stats.totals.check(trailer)?;
         }
+        Some(Err(e)) => Err(e.clone())?,
+        None if args.require_trailer => Err(ControlTotalsError::MissingTrailer)?,
+        None => {}
     }
 
     Ok(())
 }
 
-/// Build report from results, and write report to stdout
-fn report_to_std_out(snapshots: &AccountSnapshots) -> Result {
-    let report = snapshots.build_report()?;
-    log::debug!("Successfully built reports for {} accounts", report.len());
+/// Build a report for each account in order, and write it to stdout or the output file
+fn write_report(args: &Args, snapshots: &AccountSnapshots) -> Result {
+    let unwritable = || exit::unwritable_output(args.output.as_deref());
+
+    let mut sink = writer::build_report_sink(
+        args.output.as_deref(),
+        args.output_format,
+        args.json_numbers,
+    )
+    .with_context(unwritable)?;
 
-    let mut wtr = writer::build_csv_writer();
+    log::debug!(
+        "Serializing reports as {:?} in {:?} order...",
+        args.output_format,
+        args.report_order
+    );
+    for account_report in snapshots.reports(args.report_order) {
+        let account_report = account_report?;
 
-    log::debug!("Serializing reports...");
-    for account_report in report.iter() {
         log::debug!("Serializing report: {account_report:?}");
-        wtr.serialize(account_report)?;
+        sink.write(&account_report).with_context(unwritable)?;
     }
 
-    let output = writer::write_to_string(wtr)?;
-
-    log::debug!("Writing to stdout: {output:?}");
-    println!("{}", output);
+    sink.finish().with_context(unwritable)?;
 
     Ok(())
 }
@@ -0,0 +1,98 @@
+use crate::exit;
+
+use tpe::outcome::{Outcome, OutcomeStatus};
+use tpe::Result;
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use csv::Writer;
+use serde::Serialize;
+
+/// An outcome as it's written to the file, with the account's balances in their own columns.
+/// Fields that aren't known for the row, ie. the client of a row that couldn't be read, are empty.
+#[derive(Serialize, Debug)]
+struct OutcomeRow {
+    line: u64,
+    ledger_index: Option<usize>,
+    tx: Option<u32>,
+    client: Option<u16>,
+    status: OutcomeStatus,
+    reason: Option<&'static str>,
+    available: Option<String>,
+    held: Option<String>,
+    total: Option<String>,
+    locked: Option<bool>,
+}
+
+impl OutcomeRow {
+    fn new(line: u64, outcome: Outcome) -> Self {
+        let account = outcome.account;
+
+        Self {
+            line,
+            ledger_index: outcome.ledger_index,
+            tx: outcome.tx,
+            client: outcome.client,
+            status: outcome.status,
+            reason: outcome.reason,
+            available: account.as_ref().map(|report| report.available.clone()),
+            held: account.as_ref().map(|report| report.held.clone()),
+            total: account.as_ref().map(|report| report.total.clone()),
+            locked: account.map(|report| report.locked),
+        }
+    }
+}
+
+/// Writes the outcome of every row to a CSV file, if there is one
+pub struct Outcomes {
+    wtr: Option<(Writer<File>, PathBuf)>,
+}
+
+impl Outcomes {
+    pub fn new(path: Option<&Path>) -> Result<Self> {
+        let wtr = match path {
+            Some(path) => {
+                let wtr =
+                    Writer::from_path(path).with_context(|| exit::unwritable_output(Some(path)))?;
+
+                Some((wtr, path.to_path_buf()))
+            }
+            None => None,
+        };
+
+        Ok(Self { wtr })
+    }
+
+    /// Outcomes are only built when they're written, so they cost nothing otherwise
+    pub fn record(&mut self, line: u64, build: impl FnOnce() -> Result<Outcome>) -> Result {
+        if let Some((wtr, path)) = &mut self.wtr {
+            wtr.serialize(OutcomeRow::new(line, build()?))
+                .with_context(|| exit::unwritable_output(Some(path)))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result {
+        if let Some((wtr, path)) = &mut self.wtr {
+            wtr.flush()
+                .with_context(|| exit::unwritable_output(Some(path)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the outcomes file, for when none of the rows were committed
+    pub fn discard(self) -> Result {
+        if let Some((wtr, path)) = self.wtr {
+            drop(wtr);
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Decodes and parses records on worker threads, ahead of the engine.
+//!
+//! One thread reads raw records from the source in chunks, and hands each chunk to the next free
+//! worker. Workers decode the records and parse their transactions, then the parsed chunks are put
+//! back into input order, so the engine applies transactions exactly as if they'd been parsed
+//! one at a time.
+
+use crate::reader::{InputRow, RawInput, RawRecord};
+
+use tpe::control::ControlTotals;
+use tpe::{Result, Transaction};
+
+use std::{
+    collections::BTreeMap,
+    panic,
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    vec,
+};
+
+/// How many records are read before they're handed to a worker
+pub const CHUNK_SIZE: usize = 1024;
+
+/// A record after it's been decoded, and its transaction parsed
+#[derive(Debug)]
+pub struct ParsedRecord {
+    pub line: u64,
+
+    /// The record as it appeared in the input, or empty if it couldn't be read
+    pub raw: RawInput,
+
+    /// Fails if the record couldn't be decoded
+    pub row: Result<ParsedRow>,
+}
+
+/// What a parsed record holds
+#[derive(Debug)]
+pub enum ParsedRow {
+    /// Fails if the record was decoded, but isn't a valid transaction
+    Transaction {
+        batch: Option<String>,
+        tx: Result<Transaction>,
+    },
+
+    /// A control record closing the batch, with the totals expected for it
+    Trailer(ControlTotals),
+}
+
+/// Decodes a raw record, and parses its transaction
+pub fn parse(raw: RawRecord) -> ParsedRecord {
+    let record = raw.decode();
+
+    let row = record.row.map(|row| match row {
+        InputRow::Event(input_event) => ParsedRow::Transaction {
+            batch: input_event.batch.clone(),
+            tx: input_event.parse_transaction(),
+        },
+        InputRow::Transaction(tx) => ParsedRow::Transaction {
+            batch: None,
+            tx: Ok(tx),
+        },
+        InputRow::Trailer(trailer) => ParsedRow::Trailer(trailer),
+    });
+
+    ParsedRecord {
+        line: record.line,
+        raw: record.raw,
+        row,
+    }
+}
+
+/// Parses the records across the given number of worker threads, yielding them in input order.
+/// With a single thread, records are parsed as they're read instead.
+pub fn parse_records(
+    records: Box<dyn Iterator<Item = RawRecord> + Send>,
+    threads: usize,
+) -> Box<dyn Iterator<Item = ParsedRecord>> {
+    if threads <= 1 {
+        return Box::new(records.map(parse));
+    }
+
+    log::debug!("Parsing records across {threads} threads");
+
+    // Bounded, so a slow engine holds back reading rather than buffering the whole input
+    let (chunk_tx, chunk_rx) = mpsc::sync_channel::<(usize, Vec<RawRecord>)>(threads * 2);
+    let (parsed_tx, parsed_rx) = mpsc::sync_channel(threads * 2);
+
+    let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+
+    let mut handles: Vec<JoinHandle<()>> = (0..threads)
+        .map(|_| {
+            let chunk_rx = chunk_rx.clone();
+            let parsed_tx = parsed_tx.clone();
+
+            thread::spawn(move || loop {
+                let chunk = chunk_rx
+                    .lock()
+                    .expect("no worker panics holding the lock")
+                    .recv();
+
+                let Ok((seq, chunk)) = chunk else {
+                    break;
+                };
+
+                let parsed: Vec<ParsedRecord> = chunk.into_iter().map(parse).collect();
+
+                // The engine stopped early, so nothing else is needed
+                if parsed_tx.send((seq, parsed)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+
+    handles.push(thread::spawn(move || {
+        let mut records = records;
+
+        for seq in 0.. {
+            let chunk: Vec<RawRecord> = records.by_ref().take(CHUNK_SIZE).collect();
+
+            if chunk.is_empty() || chunk_tx.send((seq, chunk)).is_err() {
+                break;
+            }
+        }
+    }));
+
+    Box::new(InOrder {
+        parsed: parsed_rx,
+        pending: BTreeMap::new(),
+        next_seq: 0,
+        current: vec![].into_iter(),
+        handles,
+    })
+}
+
+/// Puts parsed chunks back into the order they were read
+struct InOrder {
+    parsed: mpsc::Receiver<(usize, Vec<ParsedRecord>)>,
+
+    /// Chunks that arrived before the ones read ahead of them
+    pending: BTreeMap<usize, Vec<ParsedRecord>>,
+
+    next_seq: usize,
+    current: vec::IntoIter<ParsedRecord>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Iterator for InOrder {
+    type Item = ParsedRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+
+            if let Some(chunk) = self.pending.remove(&self.next_seq) {
+                self.current = chunk.into_iter();
+                self.next_seq += 1;
+                continue;
+            }
+
+            match self.parsed.recv() {
+                Ok((seq, chunk)) => {
+                    self.pending.insert(seq, chunk);
+                }
+                Err(_) => {
+                    // Every thread has finished. Make sure none stopped early,
+                    // rather than silently dropping the rest of the input
+                    for handle in self.handles.drain(..) {
+                        if let Err(e) = handle.join() {
+                            panic::resume_unwind(e);
+                        }
+                    }
+
+                    return None;
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,402 @@
-use tpe::Result;
+use crate::args::{Args, Input};
+use crate::camt;
+use crate::dialect::{ColumnMapping, CsvDialect};
 
-use std::{fs::File, path::PathBuf};
+use tpe::binary::{BinaryReader, BinaryRecord};
+use tpe::checkpoint::{self, Checkpoint};
+use tpe::control::{ControlTotals, ControlTotalsError};
+use tpe::input::InputEvent;
+use tpe::source::{FileSource, StdinSource, TransactionSource};
+use tpe::{AccountReport, Result, Transaction};
 
-use csv::{Reader, ReaderBuilder, Trim};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    iter,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
-/// Builds an empty csv reader
-pub fn build_csv_reader(filepath: PathBuf) -> Result<Reader<File>> {
-    let reader = ReaderBuilder::new().trim(Trim::All).from_path(filepath)?;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
 
-    Ok(reader)
+use serde_json::Value;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum JsonLinesError {
+    #[error("Invalid JSON Lines record: {0}")]
+    InvalidRecord(serde_json::Error),
+}
+
+/// Supported formats for input transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    JsonLines,
+
+    /// ISO 20022 camt.053 bank statements
+    Camt053,
+
+    /// Fixed-width binary transactions, see `tpe::binary`
+    Binary,
+}
+
+/// A single input record, along with the line it was read from
+#[derive(Debug)]
+pub struct InputRecord {
+    pub line: u64,
+
+    /// The record as it appeared in the input, or empty if it couldn't be read
+    pub raw: RawInput,
+
+    pub row: Result<InputRow>,
+}
+
+/// A record as it appeared in the input.
+/// Binary records are kept as bytes, and only encoded as text when they're written out.
+#[derive(Debug, Clone)]
+pub enum RawInput {
+    Text(String),
+    Binary(BinaryRecord),
+}
+
+impl RawInput {
+    /// The record as text, with binary records hex-encoded
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            RawInput::Text(text) => Cow::Borrowed(text),
+            RawInput::Binary(record) => Cow::Owned(record.to_hex()),
+        }
+    }
+}
+
+impl Default for RawInput {
+    fn default() -> Self {
+        RawInput::Text(String::new())
+    }
+}
+
+impl From<String> for RawInput {
+    fn from(text: String) -> Self {
+        RawInput::Text(text)
+    }
+}
+
+/// What an input record holds
+#[derive(Debug)]
+pub enum InputRow {
+    Event(InputEvent),
+
+    /// A transaction decoded directly, without an InputEvent to parse
+    Transaction(Transaction),
+
+    /// A control record closing the batch, with the totals expected for it
+    Trailer(ControlTotals),
+}
+
+/// Opens the transaction source for the given input
+pub fn open_source(input: &Input) -> Result<Box<dyn TransactionSource>> {
+    let source: Box<dyn TransactionSource> = match input {
+        Input::Stdin => Box::new(StdinSource::new()),
+        Input::File(path) => Box::new(FileSource::open(path)?),
+    };
+
+    Ok(source)
+}
+
+/// A record as it was read, before it's decoded.
+/// Decoding is kept separate from reading, so it can run on another thread.
+#[derive(Debug)]
+pub enum RawRecord {
+    Csv {
+        record: StringRecord,
+
+        /// The record's text exactly as it was read, without its line terminator
+        text: String,
+
+        mapping: Arc<ColumnMapping>,
+    },
+
+    JsonLine {
+        line: u64,
+        text: String,
+    },
+
+    /// A record that had to be decoded as it was read
+    Decoded(InputRecord),
+}
+
+impl RawRecord {
+    pub fn decode(self) -> InputRecord {
+        match self {
+            RawRecord::Csv {
+                mut record,
+                text,
+                mapping,
+            } => {
+                record.trim();
+
+                let row = match mapping.trailer_fields(&record) {
+                    Some(fields) => parse_trailer(&fields).map(InputRow::Trailer),
+                    None => mapping
+                        .map_record(&record)
+                        .deserialize(Some(mapping.headers()))
+                        .map(InputRow::Event)
+                        .map_err(Into::into),
+                };
+
+                InputRecord {
+                    line: record.position().map(|pos| pos.line()).unwrap_or(0),
+                    raw: text.into(),
+                    row,
+                }
+            }
+            RawRecord::JsonLine { line, text } => InputRecord {
+                line,
+                row: parse_json_line(&text),
+                raw: text.into(),
+            },
+            RawRecord::Decoded(record) => record,
+        }
+    }
+}
+
+/// Reads the source as a stream of raw records in the format given by the args
+pub fn read_raw_records(
+    source: Box<dyn TransactionSource>,
+    args: &Args,
+) -> Result<Box<dyn Iterator<Item = RawRecord> + Send>> {
+    let records: Box<dyn Iterator<Item = RawRecord> + Send> = match args.input_format {
+        InputFormat::Csv => Box::new(read_csv_records(source, &args.csv_dialect)?),
+        InputFormat::JsonLines => Box::new(read_json_lines_records(source)),
+        InputFormat::Camt053 => {
+            Box::new(camt::read_camt_records(source, &args.camt_mapping)?.map(RawRecord::Decoded))
+        }
+        InputFormat::Binary => Box::new(read_binary_records(source)?.map(RawRecord::Decoded)),
+    };
+
+    Ok(records)
+}
+
+fn read_csv_records<R: Read>(
+    source: R,
+    dialect: &CsvDialect,
+) -> Result<impl Iterator<Item = RawRecord>> {
+    let read = Arc::new(Mutex::new(ReadBytes::default()));
+
+    let mut rdr = dialect.reader_builder().from_reader(Recorded {
+        inner: source,
+        read: read.clone(),
+    });
+
+    let mapping = if dialect.has_headers {
+        let mut headers = rdr.headers()?.clone();
+        headers.trim();
+
+        dialect.build_mapping(Some(&headers))?
+    } else {
+        dialect.build_mapping(None)?
+    };
+
+    let mapping = Arc::new(mapping);
+    let mut records = rdr.into_records();
+
+    let records = iter::from_fn(move || {
+        let record = records.next()?;
+
+        // The reader is left at the start of the next record, so this one ends there
+        let end = records.reader().position().byte();
+        let mut read = read.lock().expect("only the reading thread holds the lock");
+
+        let record = match record {
+            Ok(record) => {
+                let start = record.position().map_or(end, |pos| pos.byte());
+
+                RawRecord::Csv {
+                    text: read.take(start, end),
+                    record,
+                    mapping: mapping.clone(),
+                }
+            }
+            Err(e) => {
+                read.take(end, end);
+
+                RawRecord::Decoded(InputRecord {
+                    line: e.position().map(|pos| pos.line()).unwrap_or(0),
+                    raw: RawInput::default(),
+                    row: Err(e.into()),
+                })
+            }
+        };
+
+        Some(record)
+    });
+
+    Ok(records)
+}
+
+/// Keeps the bytes read from a source, so records can be taken exactly as they were read
+struct Recorded<R> {
+    inner: R,
+    read: Arc<Mutex<ReadBytes>>,
+}
+
+impl<R: Read> Read for Recorded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+
+        self.read
+            .lock()
+            .expect("only the reading thread holds the lock")
+            .bytes
+            .extend_from_slice(&buf[..len]);
+
+        Ok(len)
+    }
+}
+
+/// Bytes read from a source that haven't been taken yet
+#[derive(Default)]
+struct ReadBytes {
+    bytes: Vec<u8>,
+
+    /// Position of the first byte in the whole source
+    offset: u64,
+}
+
+impl ReadBytes {
+    /// Takes the text between two positions in the source, without line terminators. A
+    /// record read after a CRLF can start on the previous line's `\n`, so both ends are trimmed.
+    /// Everything before the end is dropped, since records are taken in order.
+    fn take(&mut self, start: u64, end: u64) -> String {
+        let to_idx = |pos: u64| (pos.saturating_sub(self.offset) as usize).min(self.bytes.len());
+        let (start, end_idx) = (to_idx(start), to_idx(end));
+
+        let text = String::from_utf8_lossy(&self.bytes[start..end_idx])
+            .trim_matches(['\r', '\n'])
+            .to_string();
+
+        self.bytes.drain(..end_idx);
+        self.offset += end_idx as u64;
+
+        text
+    }
+}
+
+/// Reads binary records, numbering them from 1 in place of line numbers
+fn read_binary_records<R: Read>(source: R) -> Result<impl Iterator<Item = InputRecord>> {
+    let records = BinaryReader::new(BufReader::new(source))?
+        .zip(1..)
+        .map(|((raw, tx), number)| InputRecord {
+            line: number,
+            raw: RawInput::Binary(raw),
+            row: tx.map(InputRow::Transaction),
+        });
+
+    Ok(records)
+}
+
+fn read_json_lines_records<R: Read>(source: R) -> impl Iterator<Item = RawRecord> {
+    BufReader::new(source)
+        .lines()
+        .zip(1..)
+        .filter(|(line, _)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(line, number)| match line {
+            Ok(text) => RawRecord::JsonLine { line: number, text },
+            Err(e) => RawRecord::Decoded(InputRecord {
+                line: number,
+                raw: RawInput::default(),
+                row: Err(e.into()),
+            }),
+        })
+}
+
+/// Deserializes a JSON object into an InputEvent, or a trailer
+fn parse_json_line(line: &str) -> Result<InputRow> {
+    let value: Value = serde_json::from_str(line).map_err(JsonLinesError::InvalidRecord)?;
+
+    if value.get("type").and_then(Value::as_str) == Some("trailer") {
+        let field = |name| value.get(name).map(json_to_string).unwrap_or_default();
+
+        let totals = ControlTotals::parse(
+            &field("record_count"),
+            &field("total_deposits"),
+            &field("total_withdrawals"),
+        )?;
+
+        return Ok(InputRow::Trailer(totals));
+    }
+
+    let event = InputEvent::from_json(value).map_err(JsonLinesError::InvalidRecord)?;
+
+    Ok(InputRow::Event(event))
+}
+
+fn json_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Parses the fields of a CSV trailer: record count, total deposits, and total withdrawals.
+/// Trailing empty fields are ignored, so trailers can share the transaction columns.
+fn parse_trailer(fields: &[&str]) -> Result<ControlTotals> {
+    let mut fields = fields.to_vec();
+
+    while fields.last() == Some(&"") {
+        fields.pop();
+    }
+
+    match fields[..] {
+        [record_count, total_deposits, total_withdrawals] => {
+            ControlTotals::parse(record_count, total_deposits, total_withdrawals)
+        }
+        _ => Err(ControlTotalsError::InvalidRecord(format!(
+            "trailer must have a record count, total deposits, and total withdrawals. Found: {fields:?}"
+        )))?,
+    }
+}
+
+/// Reads control totals from a manifest file, with the headers
+/// `record_count,total_deposits,total_withdrawals` and a single row
+pub fn read_manifest(path: &Path) -> Result<ControlTotals> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+
+    let manifest: Manifest = rdr
+        .deserialize()
+        .next()
+        .ok_or_else(|| ControlTotalsError::InvalidRecord(format!("empty manifest: {path:?}")))??;
+
+    ControlTotals::parse(
+        &manifest.record_count,
+        &manifest.total_deposits,
+        &manifest.total_withdrawals,
+    )
+}
+
+/// Reads accounts in the same CSV format as the report
+pub fn read_accounts(path: &Path) -> Result<Vec<AccountReport>> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+
+    let accounts = rdr
+        .deserialize()
+        .collect::<csv::Result<Vec<AccountReport>>>()?;
+
+    Ok(accounts)
+}
+
+/// Reads a checkpoint written by an earlier run
+pub fn read_checkpoint(path: &Path) -> Result<Checkpoint> {
+    checkpoint::read(BufReader::new(File::open(path)?))
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    record_count: String,
+    total_deposits: String,
+    total_withdrawals: String,
 }
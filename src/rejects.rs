@@ -0,0 +1,188 @@
+use crate::batch::BatchError;
+use crate::camt::CamtError;
+use crate::exit::{self, ProcessError};
+use crate::reader::RawInput;
+use crate::serve::ServeError;
+
+use tpe::outcome::{Outcome, OutcomeStatus};
+use tpe::rejection;
+use tpe::{Result, Transaction};
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use csv::Writer;
+use serde::Serialize;
+
+pub use tpe::rejection::RejectStage;
+
+/// A rejected input record, written so it can be fixed and resubmitted
+#[derive(Serialize, Debug)]
+pub struct Reject<'a> {
+    pub line: u64,
+    pub stage: RejectStage,
+    pub reason: &'static str,
+    pub message: String,
+    pub record: Cow<'a, str>,
+}
+
+impl<'a> Reject<'a> {
+    pub fn new(line: u64, record: &'a RawInput, stage: RejectStage, error: &anyhow::Error) -> Self {
+        Self {
+            line,
+            stage,
+            reason: reason_code(stage, error),
+            message: format!("{error:#}"),
+            record: record.text(),
+        }
+    }
+
+    /// The record's transaction was rejected when it was applied, if its outcome says so
+    pub fn from_outcome(line: u64, record: &'a RawInput, outcome: &Outcome) -> Option<Self> {
+        if outcome.status != OutcomeStatus::Rejected {
+            return None;
+        }
+
+        Some(Self {
+            line,
+            stage: RejectStage::Apply,
+            reason: outcome.reason.unwrap_or(RejectStage::Apply.code()),
+            message: outcome.message.clone().unwrap_or_default(),
+            record: record.text(),
+        })
+    }
+
+    /// The outcome of the rejected record.
+    /// The transaction is only known if the record was parsed before it was rejected.
+    pub fn outcome(&self, tx: Option<&Transaction>) -> Outcome {
+        Outcome::rejected(tx, self.reason, self.message.clone())
+    }
+}
+
+/// Finds the machine-readable reason for an error, falling back to a code for the stage
+pub fn reason_code(stage: RejectStage, error: &anyhow::Error) -> &'static str {
+    if let Some(code) = rejection::error_code(error) {
+        return code;
+    }
+
+    if let Some(e) = error.downcast_ref::<BatchError>() {
+        return e.code();
+    }
+
+    if let Some(e) = error.downcast_ref::<CamtError>() {
+        return e.code();
+    }
+
+    if let Some(e) = error.downcast_ref::<ServeError>() {
+        return e.code();
+    }
+
+    stage.code()
+}
+
+/// Writes rejected records to a CSV dead-letter file
+pub struct RejectsWriter {
+    wtr: Writer<File>,
+    path: PathBuf,
+}
+
+impl RejectsWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let wtr = Writer::from_path(path).with_context(|| exit::unwritable_output(Some(path)))?;
+
+        Ok(Self {
+            wtr,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn write(&mut self, reject: &Reject) -> Result {
+        self.wtr
+            .serialize(reject)
+            .with_context(|| exit::unwritable_output(Some(&self.path)))?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result {
+        self.wtr
+            .flush()
+            .with_context(|| exit::unwritable_output(Some(&self.path)))?;
+
+        Ok(())
+    }
+}
+
+/// Tracks rejected records, writing them to the rejects file if there is one
+pub struct Rejects {
+    writer: Option<RejectsWriter>,
+    strict: bool,
+    count: usize,
+    by_stage: BTreeMap<RejectStage, usize>,
+}
+
+impl Rejects {
+    /// In strict mode, the first rejected record is returned as an error
+    pub fn new(path: Option<&Path>, strict: bool) -> Result<Self> {
+        let writer = match path {
+            Some(path) => Some(RejectsWriter::create(path)?),
+            None => None,
+        };
+
+        let by_stage = [
+            RejectStage::Deserialize,
+            RejectStage::Parse,
+            RejectStage::Apply,
+        ]
+        .into_iter()
+        .map(|stage| (stage, 0))
+        .collect();
+
+        Ok(Self {
+            writer,
+            strict,
+            count: 0,
+            by_stage,
+        })
+    }
+
+    pub fn reject(&mut self, reject: Reject) -> Result {
+        log::warn!("Line {}: {}", reject.line, reject.message);
+
+        self.count += 1;
+        *self.by_stage.entry(reject.stage).or_default() += 1;
+
+        if let Some(writer) = &mut self.writer {
+            writer.write(&reject)?;
+        }
+
+        if self.strict {
+            Err(ProcessError::Rejected(reject.line, reject.message))?;
+        }
+
+        Ok(())
+    }
+
+    /// How many records have been rejected
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// How many records each stage has rejected
+    pub fn count_by_stage(&self) -> &BTreeMap<RejectStage, usize> {
+        &self.by_stage
+    }
+
+    pub fn flush(&mut self) -> Result {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
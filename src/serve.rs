@@ -0,0 +1,355 @@
+//! Serves a single engine over TCP, so many clients can stream transactions into it at once.
+//!
+//! Each connection sends CSV rows in the input dialect, one per line, starting with a header row
+//! unless the dialect has none. A line of just `REPORT` is answered with the account report as
+//! CSV, followed by an empty line.
+//!
+//! Connections parse their own rows, then hand them to a single engine thread, which applies them
+//! in the order they arrive. So rows from the same connection are applied in the order they were
+//! sent, and a `REPORT` includes every row sent before it on the same connection. Rows are
+//! numbered across every connection in the order they're applied, and those numbers are used in
+//! place of line numbers for rejects and outcomes.
+//!
+//! Rows wait in a bounded queue for the engine, so a connection sending faster than rows are
+//! applied is held back rather than buffered. At most [`MAX_CONNECTIONS`] are handled at once,
+//! and further connections wait to be accepted until one closes.
+//!
+//! The engine only stops if it fails, ie. when an output can't be written or the ledger is left in
+//! an invalid state. That stops the server for every connection, with the exit status a run
+//! stopped by the same error would have.
+
+use crate::args::Args;
+use crate::dialect::CsvDialect;
+use crate::exit::ExitStatus;
+use crate::outcomes::Outcomes;
+use crate::pipeline::{self, ParsedRecord, ParsedRow};
+use crate::reader::RawRecord;
+use crate::rejects::{Reject, RejectStage, Rejects};
+
+use tpe::report::{CsvSink, ReportSink};
+use tpe::{AccountSnapshots, Ledger, Result};
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use thiserror::Error;
+
+/// The command a connection sends to get the account report
+pub const REPORT_COMMAND: &str = "REPORT";
+
+/// How many connections are handled at once
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How many requests can wait for the engine, before connections are held back
+pub const QUEUE_SIZE: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum ServeError {
+    #[error("Batches aren't supported when serving, rows are applied as they arrive")]
+    BatchUnsupported,
+
+    #[error("Control trailers aren't supported when serving, there's no end of input to check")]
+    TrailerUnsupported,
+}
+
+impl ServeError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServeError::BatchUnsupported => "batch_unsupported",
+            ServeError::TrailerUnsupported => "trailer_unsupported",
+        }
+    }
+}
+
+/// What a connection asks of the engine
+enum Request {
+    Row(ParsedRecord),
+
+    /// Answered with the report as CSV
+    Report(Sender<Vec<u8>>),
+}
+
+/// Listens on the address, and applies rows from every connection until the engine fails.
+/// The address actually listened on is written to stdout, so port `0` can be used.
+/// Only returns once the engine stops, with its error if it failed.
+pub fn serve(
+    listen: SocketAddr,
+    args: &Args,
+    ledger: Ledger,
+    snapshots: AccountSnapshots,
+) -> Result<ExitStatus> {
+    let listener = TcpListener::bind(listen)?;
+    let local_addr = listener.local_addr()?;
+
+    println!("Listening on {local_addr}");
+
+    let (requests_tx, requests_rx) = mpsc::sync_channel(QUEUE_SIZE);
+    let dialect = Arc::new(args.csv_dialect.clone());
+    let slots = Arc::new(ConnectionSlots::default());
+
+    thread::spawn(move || loop {
+        let slot = slots.acquire();
+
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        let requests = requests_tx.clone();
+        let dialect = dialect.clone();
+
+        thread::spawn(move || {
+            // Freed once the connection is done with, however it ends
+            let _slot = slot;
+
+            let peer = stream.peer_addr().ok();
+            log::debug!("Accepted connection from {peer:?}");
+
+            if let Err(e) = handle_connection(stream, &dialect, requests) {
+                log::warn!("Connection from {peer:?} closed: {e}");
+            }
+        });
+    });
+
+    match run_engine(requests_rx, args, ledger, snapshots) {
+        Ok(()) => Ok(ExitStatus::Success),
+        // Returned rather than reported as success, so the exit status is picked from the error
+        Err(e) => {
+            log::error!("Engine stopped, closing every connection");
+            Err(e)
+        }
+    }
+}
+
+/// Counts the connections being handled, so no more than [`MAX_CONNECTIONS`] are at once
+#[derive(Default)]
+struct ConnectionSlots {
+    active: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl ConnectionSlots {
+    /// Waits until fewer than [`MAX_CONNECTIONS`] are being handled, then takes a slot
+    fn acquire(self: &Arc<Self>) -> ConnectionSlot {
+        let mut active = self
+            .active
+            .lock()
+            .expect("no connection panics holding the lock");
+
+        while *active >= MAX_CONNECTIONS {
+            active = self
+                .freed
+                .wait(active)
+                .expect("no connection panics holding the lock");
+        }
+
+        *active += 1;
+
+        ConnectionSlot {
+            slots: self.clone(),
+        }
+    }
+}
+
+/// A connection's slot, freed when it's dropped
+struct ConnectionSlot {
+    slots: Arc<ConnectionSlots>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut active = self
+            .slots
+            .active
+            .lock()
+            .expect("no connection panics holding the lock");
+
+        *active -= 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+/// Reads rows and commands from a connection, until it's closed
+fn handle_connection(
+    stream: TcpStream,
+    dialect: &Arc<CsvDialect>,
+    requests: SyncSender<Request>,
+) -> Result {
+    let mut output = stream.try_clone()?;
+    let mut mapping = None;
+
+    for text in BufReader::new(stream).lines() {
+        let text = text?;
+        let text = text.trim_end_matches('\r');
+
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        if text.trim() == REPORT_COMMAND {
+            let (report_tx, report_rx) = mpsc::channel();
+            requests.send(Request::Report(report_tx))?;
+
+            output.write_all(&report_rx.recv()?)?;
+            output.write_all(b"\n")?;
+            output.flush()?;
+            continue;
+        }
+
+        let mut rdr = dialect
+            .reader_builder()
+            .has_headers(false)
+            .from_reader(text.as_bytes());
+
+        let Some(record) = rdr.records().next() else {
+            continue;
+        };
+
+        let mapping = match &mapping {
+            Some(mapping) => mapping,
+            None => {
+                let headers = match &record {
+                    Ok(record) if dialect.has_headers => {
+                        let mut headers = record.clone();
+                        headers.trim();
+                        Some(headers)
+                    }
+                    _ => None,
+                };
+
+                let mapping = mapping.insert(Arc::new(dialect.build_mapping(headers.as_ref())?));
+
+                if headers.is_some() {
+                    continue;
+                }
+
+                mapping
+            }
+        };
+
+        let parsed = match record {
+            Ok(record) => pipeline::parse(RawRecord::Csv {
+                record,
+                text: text.to_string(),
+                mapping: mapping.clone(),
+            }),
+            Err(e) => ParsedRecord {
+                line: 0,
+                raw: text.to_string().into(),
+                row: Err(e.into()),
+            },
+        };
+
+        requests.send(Request::Row(parsed))?;
+    }
+
+    Ok(())
+}
+
+/// Applies rows and answers reports as they arrive, from every connection
+fn run_engine(
+    requests: Receiver<Request>,
+    args: &Args,
+    mut ledger: Ledger,
+    mut snapshots: AccountSnapshots,
+) -> Result {
+    let mut rejects = Rejects::new(args.rejects.as_deref(), false)?;
+    let mut outcomes = Outcomes::new(args.outcomes.as_deref())?;
+
+    let mut line = 0;
+
+    loop {
+        // Flush whenever there's nothing waiting, so the files keep up without slowing the engine
+        let request = match requests.try_recv() {
+            Ok(request) => request,
+            Err(TryRecvError::Empty) => {
+                rejects.flush()?;
+                outcomes.flush()?;
+
+                match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        let record = match request {
+            Request::Row(record) => record,
+            Request::Report(reply) => {
+                let report = build_report(args, &ledger, &snapshots)?;
+
+                // The connection may have closed without waiting for its report
+                let _ = reply.send(report);
+                continue;
+            }
+        };
+
+        line += 1;
+
+        let parsed = match record.row {
+            Ok(ParsedRow::Transaction { batch: None, tx }) => {
+                tx.map_err(|e| (RejectStage::Parse, e))
+            }
+            Ok(ParsedRow::Transaction { batch: Some(_), .. }) => {
+                Err((RejectStage::Parse, ServeError::BatchUnsupported.into()))
+            }
+            Ok(ParsedRow::Trailer(_)) => {
+                Err((RejectStage::Parse, ServeError::TrailerUnsupported.into()))
+            }
+            Err(e) => Err((RejectStage::Deserialize, e)),
+        };
+
+        let tx = match parsed {
+            Ok(tx) => tx,
+            Err((stage, e)) => {
+                let reject = Reject::new(line, &record.raw, stage, &e);
+                outcomes.record(line, || Ok(reject.outcome(None)))?;
+                rejects.reject(reject)?;
+                continue;
+            }
+        };
+
+        crate::apply_transaction(
+            line,
+            &record.raw,
+            tx,
+            &mut ledger,
+            &mut snapshots,
+            &mut rejects,
+            &mut outcomes,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Builds the current report as CSV, with any disputes whose window has ended closed,
+/// so it matches the report of a run over the same rows.
+/// Disputes are only closed in the report, so asking for one never changes the accounts.
+fn build_report(args: &Args, ledger: &Ledger, snapshots: &AccountSnapshots) -> Result<Vec<u8>> {
+    let snapshots = snapshots.expired(ledger)?;
+
+    let mut output = vec![];
+    let mut sink = CsvSink::new(&mut output);
+
+    for report in snapshots.reports(args.report_order) {
+        sink.write(&report?)?;
+    }
+
+    sink.finish()?;
+    drop(sink);
+
+    Ok(output)
+}
@@ -9,4 +9,15 @@ pub struct AccountReport {
     pub held: String,
     pub total: String,
     pub locked: bool,
+
+    /// Only reported when the account policy tracks debt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owed: Option<String>,
+
+    /// Only reported when the account policy changes when accounts are locked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flagged: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_reason: Option<String>,
 }
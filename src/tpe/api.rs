@@ -0,0 +1,483 @@
+//! A JSON API over a ledger and its accounts, independent of how requests arrive.
+//!
+//! | Method | Path | |
+//! | --- | --- | --- |
+//! | `POST` | `/transactions` | Applies one `InputEvent`, or an array of them, returning the outcome of each |
+//! | `GET` | `/accounts` | Reports on every account, ordered by client |
+//! | `GET` | `/accounts/{client}` | Reports on a single account |
+//! | `GET` | `/accounts/{client}/transactions` | Lists the client's ledger entries, with running balances |
+//!
+//! Requests are handled one at a time, in the order they're given to [`Api::handle`].
+
+use crate::ids::ClientId;
+use crate::input::InputEvent;
+use crate::outcome::{self, Outcome};
+use crate::rejection::{self, is_invalid_ledger_state, RejectStage};
+use crate::statement::{EntryStatus, Statement, StatementRange};
+use crate::{AccountSnapshots, Ledger, Result, Transaction};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("Batches aren't supported, rows are applied as they arrive")]
+    BatchUnsupported,
+}
+
+impl ApiError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BatchUnsupported => "batch_unsupported",
+        }
+    }
+}
+
+/// A response to a request, with its HTTP status code and JSON body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl ApiResponse {
+    fn ok(body: impl Serialize) -> Result<Self> {
+        Ok(Self {
+            status: 200,
+            body: serde_json::to_value(body)?,
+        })
+    }
+
+    /// An error, with a machine-readable code and a message explaining it
+    pub fn error(status: u16, code: &str, message: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "error": code, "message": message.to_string() }),
+        }
+    }
+}
+
+/// The outcome of a single row of a request
+#[derive(Serialize, Debug)]
+struct RowOutcome {
+    /// Position of the row in the request
+    row: usize,
+
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+/// A ledger entry of a client, with the balances after it
+#[derive(Serialize, Debug)]
+struct TransactionEntry {
+    ledger_index: usize,
+    tx: u32,
+
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+
+    status: &'static str,
+    available: String,
+    held: String,
+    total: String,
+}
+
+/// Serves requests against a ledger and its accounts
+#[derive(Debug)]
+pub struct Api {
+    ledger: Ledger,
+    snapshots: AccountSnapshots,
+
+    /// Set once the ledger is left in an invalid state, after which every request fails
+    failure: Option<String>,
+}
+
+impl Api {
+    pub fn new(ledger: Ledger, snapshots: AccountSnapshots) -> Self {
+        Self {
+            ledger,
+            snapshots,
+            failure: None,
+        }
+    }
+
+    /// Handles a single request. `path` may include a query string, which is ignored.
+    pub fn handle(&mut self, method: &str, path: &str, body: &[u8]) -> ApiResponse {
+        if let Some(failure) = &self.failure {
+            return ApiResponse::error(500, "invalid_ledger_state", failure);
+        }
+
+        let path = path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let response = match (method, segments.as_slice()) {
+            ("POST", ["transactions"]) => self.post_transactions(body),
+            ("GET", ["accounts"]) => self.get_accounts(),
+            ("GET", ["accounts", client]) => self.get_account(client),
+            ("GET", ["accounts", client, "transactions"]) => self.get_account_transactions(client),
+
+            (_, ["transactions"])
+            | (_, ["accounts"])
+            | (_, ["accounts", _])
+            | (_, ["accounts", _, "transactions"]) => Ok(ApiResponse::error(
+                405,
+                "method_not_allowed",
+                format!("{method} isn't allowed on {path}"),
+            )),
+
+            _ => Ok(ApiResponse::error(
+                404,
+                "not_found",
+                format!("Nothing found at {path}"),
+            )),
+        };
+
+        response.unwrap_or_else(|e| {
+            if is_invalid_ledger_state(&e) {
+                log::error!("Ledger left in an invalid state: {e:?}");
+                self.failure = Some(e.to_string());
+
+                return ApiResponse::error(500, "invalid_ledger_state", e);
+            }
+
+            ApiResponse::error(500, "internal_error", e)
+        })
+    }
+
+    fn post_transactions(&mut self, body: &[u8]) -> Result<ApiResponse> {
+        let rows = match serde_json::from_slice(body) {
+            Ok(Value::Array(rows)) => rows,
+            Ok(row @ Value::Object(_)) => vec![row],
+            Ok(_) => {
+                return Ok(ApiResponse::error(
+                    400,
+                    "invalid_body",
+                    "Expected an input event, or an array of them",
+                ))
+            }
+            Err(e) => return Ok(ApiResponse::error(400, "invalid_body", e)),
+        };
+
+        let mut outcomes = vec![];
+
+        for (row, value) in rows.into_iter().enumerate() {
+            outcomes.push(self.apply_row(row, value)?);
+        }
+
+        ApiResponse::ok(json!({ "outcomes": outcomes }))
+    }
+
+    /// Applies a single row, failing only if the ledger is left in an invalid state
+    fn apply_row(&mut self, row: usize, value: Value) -> Result<RowOutcome> {
+        let outcome = match parse_row(value) {
+            Ok(tx) => {
+                let client_id = tx.client_id;
+
+                outcome::apply_transaction(&mut self.ledger, &mut self.snapshots, tx)?
+                    .with_account(self.snapshots.find(client_id))?
+            }
+            Err((stage, e)) => {
+                Outcome::rejected(None, rejection::reason_code(stage, &e), format!("{e:#}"))
+            }
+        };
+
+        Ok(RowOutcome { row, outcome })
+    }
+
+    /// Disputes whose window has ended are only closed in the report, so reading never changes
+    /// the accounts. They're closed in the accounts by the next transaction for the account.
+    fn get_accounts(&self) -> Result<ApiResponse> {
+        ApiResponse::ok(self.snapshots.expired(&self.ledger)?.build_report()?)
+    }
+
+    fn get_account(&self, client: &str) -> Result<ApiResponse> {
+        let Some(client_id) = parse_client(client) else {
+            return Ok(invalid_client(client));
+        };
+
+        match self.snapshots.find_expired(client_id, &self.ledger)? {
+            Some(snapshot) => ApiResponse::ok(snapshot.parse_report()?),
+            None => Ok(account_not_found(client)),
+        }
+    }
+
+    fn get_account_transactions(&self, client: &str) -> Result<ApiResponse> {
+        let Some(client_id) = parse_client(client) else {
+            return Ok(invalid_client(client));
+        };
+
+        if self.snapshots.find(client_id).is_none() {
+            return Ok(account_not_found(client));
+        }
+
+        let statement = Statement::build(
+            &self.ledger,
+            &self.snapshots,
+            client_id,
+            StatementRange::default(),
+        )?;
+
+        let entries: Vec<TransactionEntry> = statement
+            .entries
+            .into_iter()
+            .map(|entry| TransactionEntry {
+                ledger_index: entry.ledger_idx,
+                tx: entry.tx_id.0,
+                tx_type: entry.tx_type.name(),
+                amount: entry.tx_type.amount().map(|amount| amount.to_string()),
+                timestamp: entry.timestamp,
+                status: match entry.status {
+                    EntryStatus::Accepted => "accepted",
+                    EntryStatus::Rejected => "rejected",
+                },
+                available: entry.balances.available.to_string(),
+                held: entry.balances.held.to_string(),
+                total: entry.balances.total.to_string(),
+            })
+            .collect();
+
+        ApiResponse::ok(entries)
+    }
+}
+
+/// Parses a row into a transaction, or the stage that rejected it and why
+fn parse_row(value: Value) -> std::result::Result<Transaction, (RejectStage, anyhow::Error)> {
+    let event = InputEvent::from_json(value).map_err(|e| (RejectStage::Deserialize, e.into()))?;
+
+    if event.batch.is_some() {
+        return Err((RejectStage::Parse, ApiError::BatchUnsupported.into()));
+    }
+
+    event
+        .parse_transaction()
+        .map_err(|e| (RejectStage::Parse, e))
+}
+
+fn parse_client(client: &str) -> Option<ClientId> {
+    client.parse().ok().map(ClientId)
+}
+
+fn invalid_client(client: &str) -> ApiResponse {
+    ApiResponse::error(
+        400,
+        "invalid_client",
+        format!("Invalid client ID: {client}"),
+    )
+}
+
+fn account_not_found(client: &str) -> ApiResponse {
+    ApiResponse::error(
+        404,
+        "account_not_found",
+        format!("No account found for client {client}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{AccountPolicy, DisputeWindow};
+
+    fn build_api() -> Api {
+        Api::new(Ledger::new(), AccountSnapshots::new())
+    }
+
+    fn post(api: &mut Api, body: &str) -> ApiResponse {
+        api.handle("POST", "/transactions", body.as_bytes())
+    }
+
+    fn get(api: &mut Api, path: &str) -> ApiResponse {
+        api.handle("GET", path, &[])
+    }
+
+    #[test]
+    fn post_transactions() {
+        let mut api = build_api();
+
+        let response = post(
+            &mut api,
+            r#"[
+                {"type": "deposit", "client": 1, "tx": 1, "amount": 2.5},
+                {"type": "withdrawal", "client": 1, "tx": 2, "amount": "5.0"},
+                {"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"},
+                {"type": "refund", "client": 1, "tx": 3}
+            ]"#,
+        );
+
+        assert_eq!(response.status, 200);
+
+        let outcomes = &response.body["outcomes"];
+
+        assert_eq!(
+            outcomes[0],
+            json!({
+                "row": 0,
+                "status": "accepted",
+                "ledger_index": 0,
+                "tx": 1,
+                "client": 1,
+                "account": {
+                    "client": "1",
+                    "available": "2.5000",
+                    "held": "0.0000",
+                    "total": "2.5000",
+                    "locked": false,
+                },
+            })
+        );
+
+        assert_eq!(outcomes[1]["status"], "rejected");
+        assert_eq!(outcomes[1]["reason"], "invalid_withdrawal");
+        assert_eq!(outcomes[1]["ledger_index"], 1);
+
+        assert_eq!(outcomes[2]["status"], "skipped");
+        assert_eq!(outcomes[2]["reason"], "duplicate");
+
+        // The same reason as an unreadable row of a JSON Lines file
+        assert_eq!(outcomes[3]["status"], "rejected");
+        assert_eq!(outcomes[3]["reason"], "malformed_record");
+        assert_eq!(outcomes[3].get("client"), None);
+    }
+
+    #[test]
+    fn post_single_transaction() {
+        let mut api = build_api();
+
+        let response = post(
+            &mut api,
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}"#,
+        );
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["outcomes"][0]["status"], "accepted");
+
+        assert_eq!(post(&mut api, "not json").status, 400);
+        assert_eq!(post(&mut api, "1").status, 400);
+    }
+
+    #[test]
+    fn get_accounts() {
+        let mut api = build_api();
+
+        post(
+            &mut api,
+            r#"[
+                {"type": "deposit", "client": 2, "tx": 1, "amount": "1.0"},
+                {"type": "deposit", "client": 1, "tx": 2, "amount": "3.0"},
+                {"type": "dispute", "client": 1, "tx": 2}
+            ]"#,
+        );
+
+        let response = get(&mut api, "/accounts");
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body[0]["client"], "1");
+        assert_eq!(response.body[0]["held"], "3.0000");
+        assert_eq!(response.body[1]["client"], "2");
+
+        let response = get(&mut api, "/accounts/2");
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["total"], "1.0000");
+
+        assert_eq!(get(&mut api, "/accounts/3").status, 404);
+        assert_eq!(get(&mut api, "/accounts/nope").status, 400);
+        assert_eq!(get(&mut api, "/nowhere").status, 404);
+        assert_eq!(api.handle("DELETE", "/accounts/2", &[]).status, 405);
+    }
+
+    #[test]
+    fn get_accounts_without_expiring_disputes() {
+        let mut api = Api::new(
+            Ledger::new(),
+            AccountSnapshots::with_policy(AccountPolicy {
+                dispute_window: DisputeWindow {
+                    max_ledger_distance: Some(1),
+                    ..DisputeWindow::default()
+                },
+                ..AccountPolicy::default()
+            }),
+        );
+
+        post(
+            &mut api,
+            r#"[
+                {"type": "deposit", "client": 1, "tx": 1, "amount": "3.0"},
+                {"type": "dispute", "client": 1, "tx": 1},
+                {"type": "deposit", "client": 2, "tx": 2, "amount": "1.0"}
+            ]"#,
+        );
+
+        // The window has ended, so the dispute is reported as resolved
+        assert_eq!(get(&mut api, "/accounts").body[0]["held"], "0.0000");
+        assert_eq!(get(&mut api, "/accounts/1").body["available"], "3.0000");
+
+        // But it's still open in the accounts, until the next transaction for the account
+        assert_eq!(api.snapshots.open_disputes()[&ClientId(1)].len(), 1);
+    }
+
+    #[test]
+    fn get_account_transactions() {
+        let mut api = build_api();
+
+        post(
+            &mut api,
+            r#"[
+                {"type": "deposit", "client": 1, "tx": 1, "amount": "3.0", "timestamp": 10},
+                {"type": "deposit", "client": 2, "tx": 2, "amount": "1.0"},
+                {"type": "withdrawal", "client": 1, "tx": 3, "amount": "5.0"},
+                {"type": "dispute", "client": 1, "tx": 1}
+            ]"#,
+        );
+
+        let response = get(&mut api, "/accounts/1/transactions?ignored=true");
+
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            json!([
+                {
+                    "ledger_index": 0,
+                    "tx": 1,
+                    "type": "deposit",
+                    "amount": "3.0000",
+                    "timestamp": 10,
+                    "status": "accepted",
+                    "available": "3.0000",
+                    "held": "0.0000",
+                    "total": "3.0000",
+                },
+                {
+                    "ledger_index": 2,
+                    "tx": 3,
+                    "type": "withdrawal",
+                    "amount": "5.0000",
+                    "status": "rejected",
+                    "available": "3.0000",
+                    "held": "0.0000",
+                    "total": "3.0000",
+                },
+                {
+                    "ledger_index": 3,
+                    "tx": 1,
+                    "type": "dispute",
+                    "status": "accepted",
+                    "available": "0.0000",
+                    "held": "3.0000",
+                    "total": "3.0000",
+                },
+            ])
+        );
+
+        assert_eq!(get(&mut api, "/accounts/3/transactions").status, 404);
+    }
+}
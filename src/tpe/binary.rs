@@ -0,0 +1,354 @@
+//! A compact, fixed-width binary encoding of transactions, for fast replays.
+//!
+//! A file starts with a 5 byte header: the magic bytes `TPEB`, then a version byte (currently 1).
+//! Every transaction after it is a 15 byte record, with integers in little-endian order:
+//!
+//! | Bytes   | Field  | Type | Notes                                                   |
+//! |---------|--------|------|---------------------------------------------------------|
+//! | `0`     | type   | u8   | See [`tag`]                                             |
+//! | `1..3`  | client | u16  |                                                         |
+//! | `3..7`  | tx     | u32  |                                                         |
+//! | `7..15` | amount | i64  | 1/100 of a cent, like [`Money`]. `0` for types without |
+//!
+//! Timestamps and batch IDs aren't encoded, so transactions with them can't be either.
+
+use crate::ids::{ClientId, TransactionId};
+use crate::{Money, Result, Transaction, TransactionType};
+
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+};
+
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"TPEB";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 5;
+pub const RECORD_LEN: usize = 15;
+
+/// Type tags for each transaction type
+pub mod tag {
+    pub const DEPOSIT: u8 = 0;
+    pub const WITHDRAWAL: u8 = 1;
+    pub const DISPUTE: u8 = 2;
+    pub const RESOLVE: u8 = 3;
+    pub const CHARGE_BACK: u8 = 4;
+    pub const WRITE_OFF: u8 = 5;
+}
+
+#[derive(Error, Debug)]
+pub enum BinaryError {
+    #[error("Invalid binary header: expected {MAGIC:?} version {VERSION}, found {0:?}")]
+    InvalidHeader(Vec<u8>),
+
+    #[error("Unknown binary transaction type tag: {0}")]
+    UnknownTag(u8),
+
+    #[error("Negative amount in binary record: {0}")]
+    NegativeAmount(Money),
+
+    #[error("Truncated binary record: expected {RECORD_LEN} bytes, found {0}")]
+    TruncatedRecord(usize),
+
+    #[error("Binary records can't hold a {0}")]
+    Unencodable(&'static str),
+}
+
+impl BinaryError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            BinaryError::InvalidHeader(_) => "invalid_header",
+            BinaryError::UnknownTag(_) => "unknown_type_tag",
+            BinaryError::NegativeAmount(_) => "negative_amount",
+            BinaryError::TruncatedRecord(_) => "truncated_record",
+            BinaryError::Unencodable(_) => "unencodable_field",
+        }
+    }
+}
+
+/// Fails if the transaction has a field that a record can't hold, so it would be lost
+pub fn check_encodable(tx: &Transaction) -> Result {
+    if tx.timestamp.is_some() {
+        Err(BinaryError::Unencodable("timestamp"))?;
+    }
+
+    Ok(())
+}
+
+/// Encodes a transaction as a fixed-width record.
+/// Only the fields a record can hold are kept, see [`check_encodable`].
+pub fn encode(tx: &Transaction) -> [u8; RECORD_LEN] {
+    let (tag, amount) = match tx.tx_type {
+        TransactionType::Deposit { amount } => (tag::DEPOSIT, amount),
+        TransactionType::Withdrawal { amount } => (tag::WITHDRAWAL, amount),
+        TransactionType::Dispute => (tag::DISPUTE, Money(0)),
+        TransactionType::Resolve => (tag::RESOLVE, Money(0)),
+        TransactionType::ChargeBack => (tag::CHARGE_BACK, Money(0)),
+        TransactionType::WriteOff => (tag::WRITE_OFF, Money(0)),
+    };
+
+    let mut record = [0; RECORD_LEN];
+
+    record[0] = tag;
+    record[1..3].copy_from_slice(&tx.client_id.0.to_le_bytes());
+    record[3..7].copy_from_slice(&tx.id.0.to_le_bytes());
+    record[7..15].copy_from_slice(&amount.0.to_le_bytes());
+
+    record
+}
+
+/// Decodes a fixed-width record into a transaction
+pub fn decode(record: &[u8; RECORD_LEN]) -> Result<Transaction> {
+    let client_id = ClientId(u16::from_le_bytes([record[1], record[2]]));
+    let id = TransactionId(u32::from_le_bytes([
+        record[3], record[4], record[5], record[6],
+    ]));
+
+    let mut amount = [0; 8];
+    amount.copy_from_slice(&record[7..15]);
+    let amount = Money(i64::from_le_bytes(amount));
+
+    let tx_type = match record[0] {
+        tag::DEPOSIT => TransactionType::Deposit { amount },
+        tag::WITHDRAWAL => TransactionType::Withdrawal { amount },
+        tag::DISPUTE => TransactionType::Dispute,
+        tag::RESOLVE => TransactionType::Resolve,
+        tag::CHARGE_BACK => TransactionType::ChargeBack,
+        tag::WRITE_OFF => TransactionType::WriteOff,
+        other => Err(BinaryError::UnknownTag(other))?,
+    };
+
+    if amount.0 < 0 {
+        Err(BinaryError::NegativeAmount(amount))?;
+    }
+
+    Ok(Transaction {
+        id,
+        client_id,
+        tx_type,
+        timestamp: None,
+        invalid: false,
+    })
+}
+
+/// Writes transactions in the binary format
+pub struct BinaryWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Writes the header, ready for transactions
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&[VERSION])?;
+
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, tx: &Transaction) -> Result {
+        self.inner.write_all(&encode(tx))?;
+
+        Ok(())
+    }
+
+    /// Flushes and returns the inner writer
+    pub fn into_inner(mut self) -> Result<W> {
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+/// A record's bytes as they were read.
+/// Shorter than a full record if the input ended part way through one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryRecord {
+    bytes: [u8; RECORD_LEN],
+    len: usize,
+}
+
+impl BinaryRecord {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// The bytes as lowercase hex, for writing the record out as text
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(self.len * 2);
+
+        for byte in self.as_bytes() {
+            write!(hex, "{byte:02x}").expect("writing to a String can't fail");
+        }
+
+        hex
+    }
+}
+
+/// Reads transactions in the binary format.
+/// Yields the raw record along with each decoded transaction, so rejected records can be kept.
+/// Records are read one at a time, so the reader should be buffered.
+pub struct BinaryReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Reads and checks the header
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        let len = read_full(&mut inner, &mut header)?;
+
+        if len != HEADER_LEN || header[..4] != MAGIC || header[4] != VERSION {
+            Err(BinaryError::InvalidHeader(header[..len].to_vec()))?;
+        }
+
+        Ok(Self { inner })
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = (BinaryRecord, Result<Transaction>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; RECORD_LEN];
+
+        let (len, tx) = match read_full(&mut self.inner, &mut bytes) {
+            Ok(0) => return None,
+            Ok(RECORD_LEN) => (RECORD_LEN, decode(&bytes)),
+            Ok(len) => (len, Err(BinaryError::TruncatedRecord(len).into())),
+            Err(e) => (0, Err(e.into())),
+        };
+
+        Some((BinaryRecord { bytes, len }, tx))
+    }
+}
+
+/// Reads until the buffer is full or the reader ends, returning how many bytes were read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOME_TRANSACTION_ID: TransactionId = TransactionId(123);
+    const SOME_CLIENT_ID: ClientId = ClientId(40);
+    const SOME_AMOUNT: Money = Money(555444);
+
+    fn build_transaction(tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: SOME_TRANSACTION_ID,
+            client_id: SOME_CLIENT_ID,
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    #[test]
+    fn encode_layout() {
+        let record = encode(&build_transaction(TransactionType::Withdrawal {
+            amount: SOME_AMOUNT,
+        }));
+
+        assert_eq!(record[0], tag::WITHDRAWAL);
+        assert_eq!(record[1..3], 40u16.to_le_bytes());
+        assert_eq!(record[3..7], 123u32.to_le_bytes());
+        assert_eq!(record[7..15], 555444i64.to_le_bytes());
+    }
+
+    #[test]
+    fn fail_to_encode_timestamp() {
+        let tx = Transaction {
+            timestamp: Some(1_700_000_000),
+            ..build_transaction(TransactionType::Dispute)
+        };
+
+        assert!(check_encodable(&build_transaction(TransactionType::Dispute)).is_ok());
+        assert!(matches!(
+            check_encodable(&tx)
+                .unwrap_err()
+                .downcast_ref::<BinaryError>(),
+            Some(BinaryError::Unencodable("timestamp"))
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let transactions = vec![
+            build_transaction(TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            }),
+            build_transaction(TransactionType::Withdrawal {
+                amount: SOME_AMOUNT,
+            }),
+            build_transaction(TransactionType::Dispute),
+            build_transaction(TransactionType::Resolve),
+            build_transaction(TransactionType::ChargeBack),
+            build_transaction(TransactionType::WriteOff),
+        ];
+
+        let mut writer = BinaryWriter::new(vec![]).unwrap();
+
+        for tx in transactions.iter() {
+            writer.write(tx).unwrap();
+        }
+
+        let bytes = writer.into_inner().unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + transactions.len() * RECORD_LEN);
+
+        let decoded: Vec<Transaction> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(|(_, tx)| tx.unwrap())
+            .collect();
+
+        assert_eq!(decoded, transactions);
+    }
+
+    #[test]
+    fn fail_to_read_invalid_header() {
+        assert!(BinaryReader::new(&b"TPEA\x01"[..]).is_err());
+        assert!(BinaryReader::new(&b"TPEB\x02"[..]).is_err());
+        assert!(BinaryReader::new(&b"TP"[..]).is_err());
+    }
+
+    #[test]
+    fn fail_to_decode_invalid_records() {
+        let mut record = encode(&build_transaction(TransactionType::Dispute));
+        record[0] = 99;
+        assert!(decode(&record).is_err());
+
+        let record = encode(&build_transaction(TransactionType::Deposit {
+            amount: Money(-1),
+        }));
+        assert!(decode(&record).is_err());
+    }
+
+    #[test]
+    fn fail_to_read_truncated_record() {
+        let mut bytes = BinaryWriter::new(vec![]).unwrap().into_inner().unwrap();
+        bytes.extend_from_slice(&[tag::DISPUTE, 1, 0]);
+
+        let mut reader = BinaryReader::new(bytes.as_slice()).unwrap();
+
+        let (raw, tx) = reader.next().unwrap();
+        assert_eq!(raw.as_bytes(), [tag::DISPUTE, 1, 0]);
+        assert_eq!(raw.to_hex(), "020100");
+        assert!(tx.is_err());
+
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,233 @@
+//! Checkpoints of the engine's full state, so a stream can be processed across separate runs.
+//!
+//! A checkpoint holds the whole ledger, including invalidated entries, along with every account
+//! snapshot and how far through the ledger it has applied. Resuming from one gives the same
+//! results as if every run's input had been processed in a single run.
+//! For that, a checkpoint must be taken before disputes are closed at the end of a run, since a
+//! later run may hold entries timestamped within their window.
+//!
+//! Checkpoints are JSON, starting with a `version` so older checkpoints can be told apart.
+
+use crate::{AccountPolicy, AccountSnapshots, Ledger, Result};
+
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The version of checkpoints written by this build. Only the same version can be read.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Unsupported checkpoint version {0}, expected version {CHECKPOINT_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error("Checkpoint was taken with a different account policy: {0:?}")]
+    PolicyMismatch(AccountPolicy),
+}
+
+impl CheckpointError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            CheckpointError::UnsupportedVersion(_) => "unsupported_checkpoint_version",
+            CheckpointError::PolicyMismatch(_) => "checkpoint_policy_mismatch",
+        }
+    }
+}
+
+/// The engine's state, as it was when the checkpoint was taken
+#[derive(Deserialize, Debug)]
+pub struct Checkpoint {
+    pub ledger: Ledger,
+    pub snapshots: AccountSnapshots,
+}
+
+impl Checkpoint {
+    /// Picks up where the checkpoint left off.
+    /// Fails unless the accounts follow the given policy, since changing it part way through
+    /// would give different results than a single run.
+    pub fn resume(self, policy: AccountPolicy) -> Result<(Ledger, AccountSnapshots)> {
+        if self.snapshots.policy() != policy {
+            Err(CheckpointError::PolicyMismatch(self.snapshots.policy()))?;
+        }
+
+        Ok((self.ledger, self.snapshots))
+    }
+}
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    version: u32,
+    ledger: &'a Ledger,
+    snapshots: &'a AccountSnapshots,
+}
+
+/// Read on its own first, so a checkpoint of another version fails on its version,
+/// rather than on whatever changed in its layout
+#[derive(Deserialize)]
+struct CheckpointHeader {
+    version: u32,
+}
+
+/// Writes a checkpoint of the ledger and snapshots
+pub fn write(mut wtr: impl Write, ledger: &Ledger, snapshots: &AccountSnapshots) -> Result {
+    let checkpoint = CheckpointRef {
+        version: CHECKPOINT_VERSION,
+        ledger,
+        snapshots,
+    };
+
+    serde_json::to_writer(&mut wtr, &checkpoint)?;
+    wtr.write_all(b"\n")?;
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Reads a checkpoint, failing if it was written with a different version
+pub fn read(mut rdr: impl Read) -> Result<Checkpoint> {
+    let mut bytes = vec![];
+    rdr.read_to_end(&mut bytes)?;
+
+    let header: CheckpointHeader = serde_json::from_slice(&bytes)?;
+
+    if header.version != CHECKPOINT_VERSION {
+        Err(CheckpointError::UnsupportedVersion(header.version))?;
+    }
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{ClientId, TransactionId};
+    use crate::{AccountPolicy, DisputeWindow, Money, Transaction, TransactionType};
+
+    const SOME_CLIENT_ID: ClientId = ClientId(40);
+    const OTHER_CLIENT_ID: ClientId = ClientId(41);
+
+    fn build_transaction(id: u32, client_id: ClientId, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id,
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    fn apply(ledger: &mut Ledger, snapshots: &mut AccountSnapshots, tx: Transaction) {
+        let client_id = tx.client_id;
+        ledger.append(tx);
+
+        let _ = snapshots
+            .find_mut_or_create(client_id)
+            .apply_transactions(ledger);
+    }
+
+    fn round_trip(ledger: &Ledger, snapshots: &AccountSnapshots) -> Checkpoint {
+        let mut bytes = vec![];
+        write(&mut bytes, ledger, snapshots).unwrap();
+
+        read(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let policy = AccountPolicy {
+            dispute_window: DisputeWindow {
+                max_ledger_distance: Some(3),
+                ..DisputeWindow::default()
+            },
+            ..AccountPolicy::default()
+        };
+
+        let first = [
+            build_transaction(
+                1,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: Money(20000),
+                },
+            ),
+            build_transaction(
+                2,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: Money(50000),
+                },
+            ),
+            build_transaction(
+                3,
+                OTHER_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: Money(10000),
+                },
+            ),
+        ];
+
+        let second = [
+            build_transaction(3, OTHER_CLIENT_ID, TransactionType::Dispute),
+            build_transaction(3, OTHER_CLIENT_ID, TransactionType::ChargeBack),
+            build_transaction(1, SOME_CLIENT_ID, TransactionType::Dispute),
+            build_transaction(1, SOME_CLIENT_ID, TransactionType::Resolve),
+        ];
+
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::with_policy(policy);
+
+        for tx in first.iter().chain(second.iter()) {
+            apply(&mut ledger, &mut snapshots, tx.clone());
+        }
+
+        let mut resumed_ledger = Ledger::new();
+        let mut resumed_snapshots = AccountSnapshots::with_policy(policy);
+
+        for tx in first.iter() {
+            apply(&mut resumed_ledger, &mut resumed_snapshots, tx.clone());
+        }
+
+        let checkpoint = round_trip(&resumed_ledger, &resumed_snapshots);
+        assert!(round_trip(&resumed_ledger, &resumed_snapshots)
+            .resume(AccountPolicy::default())
+            .is_err());
+
+        let (mut resumed_ledger, mut resumed_snapshots) = checkpoint.resume(policy).unwrap();
+        assert!(resumed_ledger.get_by_index(&1).unwrap().invalid);
+
+        for tx in second.iter() {
+            apply(&mut resumed_ledger, &mut resumed_snapshots, tx.clone());
+        }
+
+        assert_eq!(
+            resumed_snapshots.build_report().unwrap(),
+            snapshots.build_report().unwrap()
+        );
+
+        for ledger_idx in 0..ledger.len() {
+            assert_eq!(
+                resumed_ledger.get_by_index(&ledger_idx),
+                ledger.get_by_index(&ledger_idx)
+            );
+        }
+
+        // Only the later dispute was out of the window, because the ledger kept its indicies
+        assert!(!resumed_ledger.get_by_index(&4).unwrap().invalid);
+        assert!(resumed_ledger.get_by_index(&5).unwrap().invalid);
+        assert!(resumed_snapshots.find(OTHER_CLIENT_ID).unwrap().is_locked());
+    }
+
+    #[test]
+    fn fail_to_read_other_version() {
+        let err = read(b"{\"version\":0,\"ledger\":[]}".as_slice()).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<CheckpointError>(),
+            Some(CheckpointError::UnsupportedVersion(0))
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{Money, Result, Transaction, TransactionType};
+
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone)]
+pub enum ControlTotalsError {
+    #[error("Invalid control record: {0}")]
+    InvalidRecord(String),
+
+    #[error("Control totals mismatch: expected {0} of {1}, but found {2}")]
+    Mismatch(&'static str, String, String),
+
+    #[error("Control totals missing: no trailer found at the end of the batch")]
+    MissingTrailer,
+
+    #[error("Record found after the trailer at line {0}")]
+    RecordAfterTrailer(u64),
+}
+
+impl ControlTotalsError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ControlTotalsError::InvalidRecord(_) => "invalid_control_record",
+            ControlTotalsError::Mismatch(..) => "control_totals_mismatch",
+            ControlTotalsError::MissingTrailer => "missing_trailer",
+            ControlTotalsError::RecordAfterTrailer(_) => "record_after_trailer",
+        }
+    }
+}
+
+/// Totals for a batch of input records, used to detect truncated or tampered batches
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ControlTotals {
+    pub record_count: u64,
+    pub total_deposits: Money,
+    pub total_withdrawals: Money,
+}
+
+impl ControlTotals {
+    /// Parses control totals from the string values of a trailer row or manifest.
+    /// Any invalid value fails as an invalid control record.
+    pub fn parse(
+        record_count: &str,
+        total_deposits: &str,
+        total_withdrawals: &str,
+    ) -> Result<Self> {
+        let record_count = record_count.trim().parse().map_err(|_| {
+            ControlTotalsError::InvalidRecord(format!("invalid record count: {record_count}"))
+        })?;
+
+        let parse_total = |name, total: &str| {
+            Money::parse(total.trim().to_string()).map_err(|e| {
+                ControlTotalsError::InvalidRecord(format!("invalid {name}: {total}: {e}"))
+            })
+        };
+
+        Ok(Self {
+            record_count,
+            total_deposits: parse_total("total deposits", total_deposits)?,
+            total_withdrawals: parse_total("total withdrawals", total_withdrawals)?,
+        })
+    }
+
+    /// Counts a record, whether or not it could be parsed as a transaction
+    pub fn count_record(&mut self) {
+        self.record_count += 1;
+    }
+
+    /// Adds the amount of a deposit or withdrawal to its total
+    pub fn add_transaction(&mut self, tx: &Transaction) -> Result {
+        match &tx.tx_type {
+            TransactionType::Deposit { amount } => self.total_deposits.add(amount),
+            TransactionType::Withdrawal { amount } => self.total_withdrawals.add(amount),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks these totals against the expected totals
+    pub fn check(&self, expected: &ControlTotals) -> Result {
+        if self.record_count != expected.record_count {
+            Err(ControlTotalsError::Mismatch(
+                "record count",
+                expected.record_count.to_string(),
+                self.record_count.to_string(),
+            ))?;
+        }
+
+        if self.total_deposits != expected.total_deposits {
+            Err(ControlTotalsError::Mismatch(
+                "total deposits",
+                expected.total_deposits.to_string(),
+                self.total_deposits.to_string(),
+            ))?;
+        }
+
+        if self.total_withdrawals != expected.total_withdrawals {
+            Err(ControlTotalsError::Mismatch(
+                "total withdrawals",
+                expected.total_withdrawals.to_string(),
+                self.total_withdrawals.to_string(),
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{ClientId, TransactionId};
+
+    const SOME_CLIENT_ID: ClientId = ClientId(1);
+
+    fn build_transaction(id: u32, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id: SOME_CLIENT_ID,
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let totals = ControlTotals::parse("3", " 10.5", "7.25 ").unwrap();
+
+        assert_eq!(
+            totals,
+            ControlTotals {
+                record_count: 3,
+                total_deposits: Money(105000),
+                total_withdrawals: Money(72500),
+            }
+        );
+    }
+
+    #[test]
+    fn fail_to_parse_invalid_count() {
+        assert!(ControlTotals::parse("three", "0", "0").is_err());
+    }
+
+    #[test]
+    fn fail_to_parse_invalid_total() {
+        let e = ControlTotals::parse("2", "1O.0", "0").unwrap_err();
+
+        assert!(matches!(
+            e.downcast_ref::<ControlTotalsError>(),
+            Some(ControlTotalsError::InvalidRecord(_))
+        ));
+    }
+
+    #[test]
+    fn add_transactions() {
+        let mut totals = ControlTotals::default();
+
+        let transactions = [
+            build_transaction(1, TransactionType::Deposit { amount: Money(100) }),
+            build_transaction(2, TransactionType::Deposit { amount: Money(50) }),
+            build_transaction(3, TransactionType::Withdrawal { amount: Money(30) }),
+            build_transaction(1, TransactionType::Dispute),
+        ];
+
+        for tx in transactions.iter() {
+            totals.count_record();
+            totals.add_transaction(tx).unwrap();
+        }
+
+        assert_eq!(
+            totals,
+            ControlTotals {
+                record_count: 4,
+                total_deposits: Money(150),
+                total_withdrawals: Money(30),
+            }
+        );
+    }
+
+    #[test]
+    fn check_totals() {
+        let totals = ControlTotals {
+            record_count: 2,
+            total_deposits: Money(100),
+            total_withdrawals: Money(30),
+        };
+
+        assert!(totals.check(&totals).is_ok());
+
+        assert!(totals
+            .check(&ControlTotals {
+                record_count: 3,
+                ..totals
+            })
+            .is_err());
+
+        assert!(totals
+            .check(&ControlTotals {
+                total_deposits: Money(99),
+                ..totals
+            })
+            .is_err());
+
+        assert!(totals
+            .check(&ControlTotals {
+                total_withdrawals: Money(31),
+                ..totals
+            })
+            .is_err());
+    }
+}
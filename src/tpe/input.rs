@@ -5,6 +5,7 @@ use crate::Result;
 use crate::{Transaction, TransactionType};
 
 use serde::Deserialize;
+use serde_json::Value;
 
 use thiserror::Error;
 
@@ -17,6 +18,13 @@ pub struct InputEvent {
     pub client: u16,
     pub tx: u32,
     pub amount: Option<String>,
+
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+
+    /// Rows sharing a batch ID are applied together, or not at all
+    #[serde(default)]
+    pub batch: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -27,6 +35,7 @@ pub enum InputEventType {
     Dispute,
     Resolve,
     Chargeback,
+    WriteOff,
 }
 
 #[derive(Error, Debug)]
@@ -41,7 +50,31 @@ pub enum InputParseError {
     NegativeAmount(InputEvent),
 }
 
+impl InputParseError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            InputParseError::NoDepositAmount(_) => "missing_deposit_amount",
+            InputParseError::NoWithdrawalAmount(_) => "missing_withdrawal_amount",
+            InputParseError::NegativeAmount(_) => "negative_amount",
+        }
+    }
+}
+
 impl InputEvent {
+    /// Reads an InputEvent from a JSON object.
+    /// Numeric amounts are accepted, and converted to strings so they're parsed like CSV amounts,
+    /// keeping the digits they were written with.
+    pub fn from_json(mut value: Value) -> serde_json::Result<Self> {
+        if let Some(amount) = value.get_mut("amount") {
+            if let Value::Number(n) = amount {
+                *amount = Value::String(n.to_string());
+            }
+        }
+
+        serde_json::from_value(value)
+    }
+
     pub fn parse_transaction(self) -> Result<Transaction> {
         let tx = match self.typ {
             InputEventType::Deposit => {
@@ -59,6 +92,7 @@ impl InputEvent {
                     id: TransactionId(self.tx),
                     client_id: ClientId(self.client),
                     tx_type: TransactionType::Deposit { amount },
+                    timestamp: self.timestamp,
                     invalid: false,
                 }
             }
@@ -77,6 +111,7 @@ impl InputEvent {
                     id: TransactionId(self.tx),
                     client_id: ClientId(self.client),
                     tx_type: TransactionType::Withdrawal { amount },
+                    timestamp: self.timestamp,
                     invalid: false,
                 }
             }
@@ -84,18 +119,28 @@ impl InputEvent {
                 id: TransactionId(self.tx),
                 client_id: ClientId(self.client),
                 tx_type: TransactionType::Dispute,
+                timestamp: self.timestamp,
                 invalid: false,
             },
             InputEventType::Resolve => Transaction {
                 id: TransactionId(self.tx),
                 client_id: ClientId(self.client),
                 tx_type: TransactionType::Resolve,
+                timestamp: self.timestamp,
                 invalid: false,
             },
             InputEventType::Chargeback => Transaction {
                 id: TransactionId(self.tx),
                 client_id: ClientId(self.client),
                 tx_type: TransactionType::ChargeBack,
+                timestamp: self.timestamp,
+                invalid: false,
+            },
+            InputEventType::WriteOff => Transaction {
+                id: TransactionId(self.tx),
+                client_id: ClientId(self.client),
+                tx_type: TransactionType::WriteOff,
+                timestamp: self.timestamp,
                 invalid: false,
             },
         };
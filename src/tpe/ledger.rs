@@ -1,13 +1,18 @@
 use crate::ids::{ClientId, TransactionId};
-use crate::Transaction;
+use crate::{Transaction, TransactionType};
 
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
 /// Represents a WORM (Write Once, Read Many) data structure for keeping track of transactions
-#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Ledger {
     history: Vec<Transaction>,
     lookup_map: HashMap<TransactionId, Vec<usize>>,
+
+    /// Kept up to date as transactions are appended, so it's never searched for
+    latest_timestamp: Option<u64>,
 }
 
 impl Ledger {
@@ -19,6 +24,7 @@ impl Ledger {
         let id = tx.id;
         let index = self.history.len();
 
+        self.latest_timestamp = self.latest_timestamp.max(tx.timestamp);
         self.history.push(tx);
 
         if let Some(indicies) = self.lookup_map.get_mut(&id) {
@@ -81,6 +87,36 @@ impl Ledger {
         transactions
     }
 
+    /// Returns the ledger index of the first valid transaction for a transaction ID
+    pub fn get_first_valid_index(&self, id: &TransactionId) -> Option<usize> {
+        self.lookup_map
+            .get(id)?
+            .iter()
+            .copied()
+            .find(|&index| !self.history[index].invalid)
+    }
+
+    /// Returns true if the transaction is an identical re-delivery of a valid deposit or withdrawal
+    /// already in the ledger. That is, the same ID, client, type, and amount.
+    pub fn is_replay(&self, tx: &Transaction) -> bool {
+        if !matches!(
+            tx.tx_type,
+            TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+        ) {
+            return false;
+        }
+
+        self.get_first_valid_index(&tx.id)
+            .and_then(|index| self.get_by_index(&index))
+            .map(|og| og.client_id == tx.client_id && og.tx_type == tx.tx_type)
+            .unwrap_or(false)
+    }
+
+    /// Returns the most recent timestamp found in the ledger, if any
+    pub fn latest_timestamp(&self) -> Option<u64> {
+        self.latest_timestamp
+    }
+
     /// Returns vector of valid ledger indicies for a client, starting from the from_idx
     pub fn get_valid_indicies_for_client(
         &self,
@@ -109,7 +145,7 @@ impl Ledger {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Money, TransactionType};
+    use crate::Money;
 
     use super::*;
 
@@ -130,6 +166,7 @@ mod tests {
             id,
             client_id,
             tx_type,
+            timestamp: None,
             invalid: false,
         }
     }
@@ -306,6 +343,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_first_valid_index() {
+        let mut ledger = Ledger::new();
+
+        assert!(ledger.get_first_valid_index(&SOME_TRANSACTION_ID).is_none());
+
+        let transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+        ledger.append(transaction1.clone());
+        ledger.append(transaction1);
+
+        let transaction2 = build_transaction(
+            OTHER_TRANSACTION_ID,
+            OTHER_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+        ledger.append(transaction2);
+
+        assert_eq!(ledger.get_first_valid_index(&SOME_TRANSACTION_ID), Some(0));
+        assert_eq!(ledger.get_first_valid_index(&OTHER_TRANSACTION_ID), Some(2));
+
+        ledger.invalidate(&0);
+
+        assert_eq!(ledger.get_first_valid_index(&SOME_TRANSACTION_ID), Some(1));
+    }
+
+    #[test]
+    fn is_replay() {
+        let mut ledger = Ledger::new();
+
+        let deposit = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+
+        assert!(!ledger.is_replay(&deposit));
+
+        ledger.append(deposit.clone());
+
+        assert!(ledger.is_replay(&deposit));
+
+        let other_amount = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit { amount: Money(1) },
+        );
+        assert!(!ledger.is_replay(&other_amount));
+
+        let other_client = build_transaction(
+            SOME_TRANSACTION_ID,
+            OTHER_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+        assert!(!ledger.is_replay(&other_client));
+
+        let other_type = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Withdrawal {
+                amount: SOME_AMOUNT,
+            },
+        );
+        assert!(!ledger.is_replay(&other_type));
+
+        let dispute = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+        ledger.append(dispute.clone());
+        assert!(!ledger.is_replay(&dispute));
+
+        ledger.invalidate(&0);
+        assert!(!ledger.is_replay(&deposit));
+    }
+
     #[test]
     fn get_valid_indicies_for_client() {
         let mut ledger = Ledger::new();
@@ -354,4 +479,26 @@ mod tests {
             vec![0, 2]
         );
     }
+
+    #[test]
+    fn latest_timestamp() {
+        let mut ledger = Ledger::new();
+        assert_eq!(ledger.latest_timestamp(), None);
+
+        for timestamp in [Some(20), None, Some(10)] {
+            ledger.append(Transaction {
+                timestamp,
+                ..build_transaction(
+                    SOME_TRANSACTION_ID,
+                    SOME_CLIENT_ID,
+                    TransactionType::Dispute,
+                )
+            });
+        }
+
+        // Rejected entries still happened, so they count too
+        ledger.invalidate(&0);
+
+        assert_eq!(ledger.latest_timestamp(), Some(20));
+    }
 }
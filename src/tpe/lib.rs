@@ -1,5 +1,16 @@
+pub mod api;
+pub mod binary;
+pub mod checkpoint;
+pub mod control;
 pub mod ids;
 pub mod input;
+pub mod outcome;
+pub mod reconcile;
+pub mod rejection;
+pub mod report;
+pub mod source;
+pub mod statement;
+pub mod summary;
 
 mod account_report;
 mod ledger;
@@ -10,7 +21,11 @@ mod transaction;
 
 pub use account_report::AccountReport;
 pub use ledger::Ledger;
-pub use money::Money;
+pub use money::{Money, MoneyError};
 pub use result::Result;
-pub use snapshots::{AccountSnapshot, AccountSnapshots};
+pub use snapshots::{
+    AccountPolicy, AccountSnapshot, AccountSnapshots, AccountTransactionError, DebtPolicy,
+    DisputeEvent, DisputeExpiry, DisputeState, DisputeWindow, GroupRollback, LockPolicy,
+    LockReason, OpenDispute, OpeningBalanceError, ReportOrder,
+};
 pub use transaction::{Transaction, TransactionType};
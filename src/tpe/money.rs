@@ -4,7 +4,7 @@ use std::fmt;
 
 use thiserror::Error;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Error, Debug)]
 pub enum MoneyError {
@@ -18,10 +18,21 @@ pub enum MoneyError {
     Parse(&'static str, String),
 }
 
+impl MoneyError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            MoneyError::Overflow(..) => "amount_overflow",
+            MoneyError::Underflow(..) => "amount_underflow",
+            MoneyError::Parse(..) => "invalid_amount",
+        }
+    }
+}
+
 /// Money type stores money as 1/100 of a cent. This prevents issues with floating-point rounding.
 /// ie. Money(123456) represents a monetary value of 12.3456
 /// Note: Money is stored as an i64, so the inner value must fit within the bounds of an i64.
-#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Money(pub i64);
 
 impl Money {
@@ -41,15 +52,34 @@ impl Money {
             Some(dollars) => dollars,
         };
 
+        // The sign applies to the cents too, ie. -1.5 is -15000 rather than -5000
+        let (negative, dollars) = match dollars.strip_prefix('-') {
+            Some(dollars) => (true, dollars),
+            None => (false, dollars),
+        };
+
         let cents = match parts.next() {
             None => "0000".to_string(),
             Some(cents) => format!("{:0<4}", cents)[..4].to_string(),
         };
 
-        let dollars: i64 = dollars.parse()?;
+        let dollars: i64 = if dollars.is_empty() && negative {
+            0
+        } else {
+            dollars.parse()?
+        };
         let cents: i64 = cents.parse()?;
 
-        Ok(Money((dollars * 10000) + cents))
+        if dollars < 0 || cents < 0 {
+            Err(MoneyError::Parse("Misplaced sign", str_to_split.clone()))?
+        }
+
+        let value = dollars
+            .checked_mul(10000)
+            .and_then(|value| value.checked_add(cents))
+            .ok_or_else(|| MoneyError::Parse("Out of range", str_to_split.clone()))?;
+
+        Ok(Money(if negative { -value } else { value }))
     }
 
     pub fn add(&mut self, other: &Self) -> Result {
@@ -79,14 +109,15 @@ impl Money {
 
 impl fmt::Display for Money {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let string = format!("{:0>5}", self.0);
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let string = format!("{:0>5}", self.0.unsigned_abs());
 
         let pivot = string.len() - 4;
 
         let dollars = &string[..pivot];
         let cents = &string[pivot..];
 
-        write!(f, "{dollars}.{cents}")
+        write!(f, "{sign}{dollars}.{cents}")
     }
 }
 
@@ -119,6 +150,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_negative() {
+        assert_eq!(Money::parse("-1.5".to_string()).unwrap(), Money(-15000));
+        assert_eq!(Money::parse("-0.0001".to_string()).unwrap(), Money(-1));
+        assert_eq!(Money::parse("-.5".to_string()).unwrap(), Money(-5000));
+        assert_eq!(
+            Money::parse("-922337203685477.5807".to_string()).unwrap(),
+            Money(-i64::MAX)
+        );
+        assert!(Money::parse("--1".to_string()).is_err());
+        assert!(Money::parse("1.-5".to_string()).is_err());
+    }
+
     #[test]
     fn parse_only_reads_4_digits() {
         assert_eq!(Money::parse("0.123456".to_string()).unwrap(), Money(1234));
@@ -151,6 +195,9 @@ mod tests {
         assert_eq!(&Money(12345678).to_string(), "1234.5678");
         assert_eq!(&Money(543210000).to_string(), "54321.0000");
         assert_eq!(&Money::MAX.to_string(), "922337203685477.5807");
+        assert_eq!(&Money(-1).to_string(), "-0.0001");
+        assert_eq!(&Money(-15000).to_string(), "-1.5000");
+        assert_eq!(&Money::MIN.to_string(), "-922337203685477.5808");
     }
 
     #[test]
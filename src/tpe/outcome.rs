@@ -0,0 +1,178 @@
+//! What happened to a row once it reached the engine, however it was submitted.
+
+use crate::rejection::{self, is_invalid_ledger_state, RejectStage};
+use crate::{AccountReport, AccountSnapshot, AccountSnapshots, Ledger, Result, Transaction};
+
+use serde::Serialize;
+
+/// What happened to a row
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutcomeStatus {
+    Accepted,
+    Rejected,
+
+    /// Left out without being an error, ie. an identical re-delivery
+    Skipped,
+}
+
+/// What happened to a single row, and the state of its account afterwards.
+/// Fields that aren't known for the row, ie. the client of a row that couldn't be read, are left out
+#[derive(Serialize, Debug)]
+pub struct Outcome {
+    pub status: OutcomeStatus,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ledger_index: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<AccountReport>,
+}
+
+impl Outcome {
+    fn new(status: OutcomeStatus, tx: Option<&Transaction>) -> Self {
+        Self {
+            status,
+            reason: None,
+            message: None,
+            ledger_index: None,
+            tx: tx.map(|tx| tx.id.0),
+            client: tx.map(|tx| tx.client_id.0),
+            account: None,
+        }
+    }
+
+    pub fn accepted(tx: &Transaction) -> Self {
+        Self::new(OutcomeStatus::Accepted, Some(tx))
+    }
+
+    pub fn skipped(tx: &Transaction, reason: &'static str) -> Self {
+        Self {
+            reason: Some(reason),
+            ..Self::new(OutcomeStatus::Skipped, Some(tx))
+        }
+    }
+
+    /// The transaction is only known if the row was parsed before it was rejected
+    pub fn rejected(tx: Option<&Transaction>, reason: &'static str, message: String) -> Self {
+        Self {
+            reason: Some(reason),
+            message: Some(message),
+            ..Self::new(OutcomeStatus::Rejected, tx)
+        }
+    }
+
+    /// Where the row was appended to the ledger
+    pub fn at(self, ledger_idx: usize) -> Self {
+        Self {
+            ledger_index: Some(ledger_idx),
+            ..self
+        }
+    }
+
+    /// Adds the report of the account, if it exists
+    pub fn with_account(self, snapshot: Option<&AccountSnapshot>) -> Result<Self> {
+        Ok(Self {
+            account: snapshot.map(AccountSnapshot::parse_report).transpose()?,
+            ..self
+        })
+    }
+}
+
+/// Appends a transaction to the ledger and applies it to its account, unless it's an identical
+/// re-delivery of one already in the ledger.
+/// Rejected transactions are still appended, with the reason in their outcome.
+/// Fails only if the ledger is left in an invalid state.
+pub fn apply_transaction(
+    ledger: &mut Ledger,
+    snapshots: &mut AccountSnapshots,
+    tx: Transaction,
+) -> Result<Outcome> {
+    if ledger.is_replay(&tx) {
+        log::debug!("Skipping identical duplicate transaction: {tx:?}");
+        return Ok(Outcome::skipped(&tx, "duplicate"));
+    }
+
+    let client_id = tx.client_id;
+
+    log::debug!("Appending transaction to ledger: {tx:?}");
+    let ledger_idx = ledger.append(tx);
+
+    let applied = snapshots
+        .find_mut_or_create(client_id)
+        .apply_transactions(ledger);
+
+    let tx = ledger
+        .get_by_index(&ledger_idx)
+        .expect("transaction was just appended");
+
+    let outcome = match applied {
+        Ok(()) => Outcome::accepted(tx),
+        Err(e) if is_invalid_ledger_state(&e) => return Err(e),
+        Err(e) => Outcome::rejected(
+            Some(tx),
+            rejection::reason_code(RejectStage::Apply, &e),
+            format!("{e:#}"),
+        ),
+    };
+
+    Ok(outcome.at(ledger_idx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{ClientId, TransactionId};
+    use crate::{Money, TransactionType};
+
+    fn build_transaction(id: u32, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id: ClientId(1),
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    #[test]
+    fn apply_transactions() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let deposit = build_transaction(1, TransactionType::Deposit { amount: Money(5) });
+        let withdrawal = build_transaction(2, TransactionType::Withdrawal { amount: Money(9) });
+
+        let outcome = apply_transaction(&mut ledger, &mut snapshots, deposit.clone()).unwrap();
+        assert_eq!(outcome.status, OutcomeStatus::Accepted);
+        assert_eq!(outcome.ledger_index, Some(0));
+
+        let outcome = apply_transaction(&mut ledger, &mut snapshots, deposit).unwrap();
+        assert_eq!(outcome.status, OutcomeStatus::Skipped);
+        assert_eq!(outcome.reason, Some("duplicate"));
+        assert_eq!(outcome.ledger_index, None);
+
+        // Rejected, but still appended
+        let outcome = apply_transaction(&mut ledger, &mut snapshots, withdrawal).unwrap();
+        assert_eq!(outcome.status, OutcomeStatus::Rejected);
+        assert_eq!(outcome.reason, Some("invalid_withdrawal"));
+        assert_eq!(outcome.ledger_index, Some(1));
+        assert_eq!(ledger.len(), 2);
+
+        let outcome = outcome.with_account(snapshots.find(ClientId(1))).unwrap();
+        assert_eq!(outcome.account.unwrap().total, "0.0005");
+    }
+}
@@ -0,0 +1,246 @@
+//! Reconciliation of account reports against the balances they're expected to have.
+
+use crate::{AccountReport, Money, Result};
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ReconcileError {
+    #[error("Invalid client in account report: {0:?}")]
+    InvalidClient(String),
+
+    #[error("Client {0} is reported more than once")]
+    DuplicateClient(u16),
+}
+
+/// The kind of difference found between an expected and an actual account
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakKind {
+    /// Expected, but there's no account for the client
+    MissingClient,
+
+    /// There's an account for the client, but it wasn't expected
+    UnexpectedClient,
+
+    /// A field of the account doesn't have the expected value
+    Mismatch,
+}
+
+/// A single difference between the expected and actual accounts
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Break {
+    pub client: u16,
+
+    #[serde(rename = "break")]
+    pub kind: BreakKind,
+
+    pub field: Option<&'static str>,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl Break {
+    fn client(client: u16, kind: BreakKind) -> Self {
+        Self {
+            client,
+            kind,
+            field: None,
+            expected: None,
+            actual: None,
+        }
+    }
+}
+
+/// Compares the actual accounts against the expected ones field by field, ordered by client.
+/// Amounts are compared by value, so `1.5` matches `1.5000`.
+/// The optional fields are only compared when the expected account has them.
+pub fn reconcile(expected: &[AccountReport], actual: &[AccountReport]) -> Result<Vec<Break>> {
+    let expected = by_client(expected)?;
+    let mut actual = by_client(actual)?;
+
+    let mut breaks = vec![];
+
+    for (client, expected) in expected {
+        let Some(actual) = actual.remove(&client) else {
+            breaks.push(Break::client(client, BreakKind::MissingClient));
+            continue;
+        };
+
+        let fields = [
+            (
+                "available",
+                Some(&expected.available),
+                Some(&actual.available),
+                true,
+            ),
+            ("held", Some(&expected.held), Some(&actual.held), true),
+            ("total", Some(&expected.total), Some(&actual.total), true),
+            ("owed", expected.owed.as_ref(), actual.owed.as_ref(), true),
+            (
+                "lock_reason",
+                expected.lock_reason.as_ref(),
+                actual.lock_reason.as_ref(),
+                false,
+            ),
+        ];
+
+        let mut mismatches: Vec<(&'static str, String, Option<String>)> = vec![];
+
+        for (field, expected, actual, is_amount) in fields {
+            let Some(expected) = expected else {
+                continue;
+            };
+
+            let matches = match actual {
+                Some(actual) if is_amount => amounts_match(expected, actual),
+                Some(actual) => expected == actual,
+                None => false,
+            };
+
+            if !matches {
+                mismatches.push((field, expected.clone(), actual.cloned()));
+            }
+        }
+
+        if expected.locked != actual.locked {
+            mismatches.push((
+                "locked",
+                expected.locked.to_string(),
+                Some(actual.locked.to_string()),
+            ));
+        }
+
+        if let Some(flagged) = expected.flagged {
+            if Some(flagged) != actual.flagged {
+                mismatches.push((
+                    "flagged",
+                    flagged.to_string(),
+                    actual.flagged.map(|flagged| flagged.to_string()),
+                ));
+            }
+        }
+
+        breaks.extend(
+            mismatches
+                .into_iter()
+                .map(|(field, expected, actual)| Break {
+                    field: Some(field),
+                    expected: Some(expected),
+                    actual,
+                    ..Break::client(client, BreakKind::Mismatch)
+                }),
+        );
+    }
+
+    breaks.extend(
+        actual
+            .into_keys()
+            .map(|client| Break::client(client, BreakKind::UnexpectedClient)),
+    );
+
+    breaks.sort_by_key(|b| b.client);
+
+    Ok(breaks)
+}
+
+fn by_client(reports: &[AccountReport]) -> Result<BTreeMap<u16, &AccountReport>> {
+    let mut map = BTreeMap::new();
+
+    for report in reports.iter() {
+        let client = report
+            .client
+            .trim()
+            .parse()
+            .map_err(|_| ReconcileError::InvalidClient(report.client.clone()))?;
+
+        if map.insert(client, report).is_some() {
+            Err(ReconcileError::DuplicateClient(client))?;
+        }
+    }
+
+    Ok(map)
+}
+
+/// Compares amounts by value, falling back on the text if either can't be parsed
+fn amounts_match(expected: &str, actual: &str) -> bool {
+    match (
+        Money::parse(expected.trim().to_string()),
+        Money::parse(actual.trim().to_string()),
+    ) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => expected.trim() == actual.trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_report(client: &str, available: &str, locked: bool) -> AccountReport {
+        AccountReport {
+            client: client.to_string(),
+            available: available.to_string(),
+            held: "0.0000".to_string(),
+            total: available.to_string(),
+            locked,
+            owed: None,
+            flagged: None,
+            lock_reason: None,
+        }
+    }
+
+    #[test]
+    fn reconcile_matching_accounts() {
+        let expected = [
+            build_report("1", "1.5", false),
+            build_report("2", "0", true),
+        ];
+        let actual = [
+            build_report("2", "0.0000", true),
+            build_report("1", "1.5000", false),
+        ];
+
+        assert_eq!(reconcile(&expected, &actual).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reconcile_breaks() {
+        let expected = [
+            build_report("1", "1.5", false),
+            build_report("2", "2", false),
+        ];
+        let actual = [
+            build_report("1", "1.4", true),
+            build_report("3", "3", false),
+        ];
+
+        let mismatch = |field, expected: &str, actual: &str| Break {
+            field: Some(field),
+            expected: Some(expected.to_string()),
+            actual: Some(actual.to_string()),
+            ..Break::client(1, BreakKind::Mismatch)
+        };
+
+        assert_eq!(
+            reconcile(&expected, &actual).unwrap(),
+            vec![
+                mismatch("available", "1.5", "1.4"),
+                mismatch("total", "1.5", "1.4"),
+                mismatch("locked", "false", "true"),
+                Break::client(2, BreakKind::MissingClient),
+                Break::client(3, BreakKind::UnexpectedClient),
+            ]
+        );
+    }
+
+    #[test]
+    fn fail_to_reconcile_duplicate_clients() {
+        let expected = [build_report("1", "1", false), build_report("1", "2", false)];
+
+        assert!(reconcile(&expected, &[]).is_err());
+    }
+}
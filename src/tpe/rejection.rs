@@ -0,0 +1,87 @@
+//! Classifies why rows were rejected, so every way of submitting them reports the same reasons.
+
+use crate::api::ApiError;
+use crate::binary::BinaryError;
+use crate::checkpoint::CheckpointError;
+use crate::control::ControlTotalsError;
+use crate::input::InputParseError;
+use crate::{AccountTransactionError, MoneyError, OpeningBalanceError};
+
+use serde::Serialize;
+
+/// The processing step that rejected a record
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectStage {
+    /// The record couldn't be read as an InputEvent
+    Deserialize,
+
+    /// The InputEvent couldn't be parsed as a Transaction
+    Parse,
+
+    /// The Transaction couldn't be applied to its account
+    Apply,
+}
+
+impl RejectStage {
+    /// The reason for errors without a more specific code
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectStage::Deserialize => "malformed_record",
+            RejectStage::Parse => "invalid_transaction",
+            RejectStage::Apply => "rejected",
+        }
+    }
+}
+
+/// Finds the machine-readable reason for an error raised by this crate,
+/// falling back to a code for the stage
+pub fn reason_code(stage: RejectStage, error: &anyhow::Error) -> &'static str {
+    error_code(error).unwrap_or_else(|| stage.code())
+}
+
+/// Finds the machine-readable reason for an error raised by this crate, if it has one
+pub fn error_code(error: &anyhow::Error) -> Option<&'static str> {
+    if let Some(e) = error.downcast_ref::<AccountTransactionError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<InputParseError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<MoneyError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<ControlTotalsError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<BinaryError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<OpeningBalanceError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<CheckpointError>() {
+        return Some(e.code());
+    }
+
+    if let Some(e) = error.downcast_ref::<ApiError>() {
+        return Some(e.code());
+    }
+
+    None
+}
+
+/// Returns true if the error means the ledger can no longer be trusted.
+/// Only the engine's own bookkeeping raises this, never a bad row, so it's always fatal.
+pub fn is_invalid_ledger_state(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<AccountTransactionError>(),
+        Some(AccountTransactionError::InvalidLedgerState(_))
+    )
+}
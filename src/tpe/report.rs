@@ -0,0 +1,328 @@
+//! Writers for account reports, in each supported output format.
+//!
+//! Every format implements [`ReportSink`], so reports can be streamed to it one at a time.
+//! Implement it to write reports anywhere else.
+
+use crate::{AccountReport, Result};
+
+use std::{io::Write, str::FromStr};
+
+use csv::Writer;
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// Somewhere account reports are written, one at a time
+pub trait ReportSink {
+    fn write(&mut self, report: &AccountReport) -> Result;
+
+    /// Writes anything left once every report has been written, and flushes the output
+    fn finish(&mut self) -> Result;
+}
+
+/// Writes reports as CSV rows, with a header row
+pub struct CsvSink<W: Write> {
+    inner: Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: Writer::from_writer(inner),
+        }
+    }
+}
+
+impl<W: Write> ReportSink for CsvSink<W> {
+    fn write(&mut self, report: &AccountReport) -> Result {
+        self.inner.serialize(report)?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result {
+        self.inner.flush()?;
+
+        Ok(())
+    }
+}
+
+/// How the numeric fields of a report are written in JSON
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JsonNumbers {
+    /// Decimal strings, ie. `"1.5000"`, so amounts never lose precision when read back
+    #[default]
+    Strings,
+
+    /// JSON numbers, written with the same digits as the strings would have
+    Numbers,
+}
+
+/// A report as a JSON object, keeping the same field order as the CSV columns
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    client: Value,
+    available: Value,
+    held: Value,
+    total: Value,
+    locked: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owed: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flagged: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_reason: Option<&'a str>,
+}
+
+impl<'a> JsonReport<'a> {
+    fn new(report: &'a AccountReport, numbers: JsonNumbers) -> Result<Self> {
+        let number = |value: &str| -> Result<Value> {
+            Ok(match numbers {
+                JsonNumbers::Strings => Value::String(value.to_string()),
+                JsonNumbers::Numbers => Value::Number(Number::from_str(value)?),
+            })
+        };
+
+        Ok(Self {
+            client: number(&report.client)?,
+            available: number(&report.available)?,
+            held: number(&report.held)?,
+            total: number(&report.total)?,
+            locked: report.locked,
+            owed: report.owed.as_deref().map(number).transpose()?,
+            flagged: report.flagged,
+            lock_reason: report.lock_reason.as_deref(),
+        })
+    }
+}
+
+/// Writes reports as a single JSON array, with one report per line
+pub struct JsonSink<W: Write> {
+    inner: W,
+    numbers: JsonNumbers,
+    written: usize,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(inner: W, numbers: JsonNumbers) -> Self {
+        Self {
+            inner,
+            numbers,
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> ReportSink for JsonSink<W> {
+    fn write(&mut self, report: &AccountReport) -> Result {
+        let separator = if self.written == 0 { "[\n" } else { ",\n" };
+        self.inner.write_all(separator.as_bytes())?;
+
+        serde_json::to_writer(&mut self.inner, &JsonReport::new(report, self.numbers)?)?;
+        self.written += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result {
+        let end = if self.written == 0 { "[]\n" } else { "\n]\n" };
+        self.inner.write_all(end.as_bytes())?;
+        self.inner.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes reports as JSON Lines, with one JSON object per line
+pub struct JsonLinesSink<W: Write> {
+    inner: W,
+    numbers: JsonNumbers,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(inner: W, numbers: JsonNumbers) -> Self {
+        Self { inner, numbers }
+    }
+}
+
+impl<W: Write> ReportSink for JsonLinesSink<W> {
+    fn write(&mut self, report: &AccountReport) -> Result {
+        serde_json::to_writer(&mut self.inner, &JsonReport::new(report, self.numbers)?)?;
+        self.inner.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result {
+        self.inner.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes reports as a Markdown table, for pasting into documents
+pub struct MarkdownSink<W: Write> {
+    inner: W,
+    written: usize,
+}
+
+impl<W: Write> MarkdownSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    fn write_row(&mut self, cells: &[String]) -> Result {
+        writeln!(self.inner, "| {} |", cells.join(" | "))?;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, names: &[&str]) -> Result {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let divider: Vec<String> = names.iter().map(|name| "-".repeat(name.len())).collect();
+
+        self.write_row(&names)?;
+        self.write_row(&divider)
+    }
+}
+
+/// The columns every report has, before any the account policy adds
+const MARKDOWN_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+impl<W: Write> ReportSink for MarkdownSink<W> {
+    fn write(&mut self, report: &AccountReport) -> Result {
+        let mut columns: Vec<(&str, String)> = vec![
+            ("client", report.client.clone()),
+            ("available", report.available.clone()),
+            ("held", report.held.clone()),
+            ("total", report.total.clone()),
+            ("locked", report.locked.to_string()),
+        ];
+
+        if let Some(owed) = &report.owed {
+            columns.push(("owed", owed.clone()));
+        }
+
+        if let Some(flagged) = report.flagged {
+            columns.push(("flagged", flagged.to_string()));
+        }
+
+        if let Some(lock_reason) = &report.lock_reason {
+            columns.push(("lock_reason", lock_reason.clone()));
+        }
+
+        // Every report has the same columns, so the first one decides the header
+        if self.written == 0 {
+            let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+            self.write_header(&names)?;
+        }
+
+        let cells: Vec<String> = columns
+            .into_iter()
+            .map(|(_, value)| value.replace('|', "\\|"))
+            .collect();
+
+        self.write_row(&cells)?;
+        self.written += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result {
+        if self.written == 0 {
+            self.write_header(&MARKDOWN_COLUMNS)?;
+        }
+
+        self.inner.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_report(client: &str, lock_reason: Option<&str>) -> AccountReport {
+        AccountReport {
+            client: client.to_string(),
+            available: "1.5000".to_string(),
+            held: "0.2500".to_string(),
+            total: "1.7500".to_string(),
+            locked: lock_reason.is_some(),
+            owed: None,
+            flagged: None,
+            lock_reason: lock_reason.map(str::to_string),
+        }
+    }
+
+    fn write_all(mut sink: impl ReportSink, reports: &[AccountReport]) {
+        for report in reports.iter() {
+            sink.write(report).unwrap();
+        }
+
+        sink.finish().unwrap();
+    }
+
+    #[test]
+    fn write_json_strings() {
+        let mut output = vec![];
+
+        write_all(
+            JsonSink::new(&mut output, JsonNumbers::Strings),
+            &[build_report("1", None), build_report("2", None)],
+        );
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n\
+             {\"client\":\"1\",\"available\":\"1.5000\",\"held\":\"0.2500\",\"total\":\"1.7500\",\"locked\":false},\n\
+             {\"client\":\"2\",\"available\":\"1.5000\",\"held\":\"0.2500\",\"total\":\"1.7500\",\"locked\":false}\n\
+             ]\n"
+        );
+    }
+
+    #[test]
+    fn write_json_numbers() {
+        let mut output = vec![];
+
+        write_all(
+            JsonLinesSink::new(&mut output, JsonNumbers::Numbers),
+            &[build_report("1", None)],
+        );
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":1.5000,\"held\":0.2500,\"total\":1.7500,\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn write_empty_json() {
+        let mut output = vec![];
+
+        write_all(JsonSink::new(&mut output, JsonNumbers::Strings), &[]);
+
+        assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn write_markdown() {
+        let mut output = vec![];
+
+        write_all(
+            MarkdownSink::new(&mut output),
+            &[build_report("1", Some("a|b")), build_report("2", Some(""))],
+        );
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "| client | available | held | total | locked | lock_reason |\n\
+             | ------ | --------- | ---- | ----- | ------ | ----------- |\n\
+             | 1 | 1.5000 | 0.2500 | 1.7500 | true | a\\|b |\n\
+             | 2 | 1.5000 | 0.2500 | 1.7500 | true |  |\n"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Rules that govern how an account reacts to transactions
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccountPolicy {
+    pub dispute_window: DisputeWindow,
+
+    /// How many times a resolved deposit may be disputed again. Unlimited when not set.
+    pub max_redisputes: Option<u32>,
+
+    pub debt: DebtPolicy,
+
+    /// When accounts are locked after a charge back. The default policy applies when not set,
+    /// and the report only includes the lock columns when it is.
+    pub lock: Option<LockPolicy>,
+}
+
+/// How an account handles a dispute for more than its available funds
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DebtPolicy {
+    /// Available funds are allowed to go negative
+    #[default]
+    Disabled,
+
+    /// Available funds stop at zero, and the shortfall is tracked as an owed amount.
+    /// Later deposits and resolves pay down the owed amount before adding to available funds.
+    Track,
+}
+
+/// When an account is locked after a charge back
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Lock once the account has this many charge backs
+    AfterChargebacks(u32),
+
+    /// Lock once charge backs make up more than this percent of the account's deposits
+    ChargebackRatio { percent: u32 },
+
+    /// Never lock automatically, only flag the account
+    Never,
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        LockPolicy::AfterChargebacks(1)
+    }
+}
+
+/// Explains why an account was locked
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    Chargebacks {
+        count: u32,
+    },
+    ChargebackRatio {
+        chargebacks: u32,
+        deposits: u32,
+        percent: u32,
+    },
+
+    /// Already locked in the opening balances the account started from
+    OpeningBalance,
+}
+
+impl LockPolicy {
+    /// Returns the reason to lock an account with the given counts, or None if it should stay open
+    pub fn check(&self, chargebacks: u32, deposits: u32) -> Option<LockReason> {
+        match *self {
+            LockPolicy::AfterChargebacks(max) if chargebacks >= max => {
+                Some(LockReason::Chargebacks { count: chargebacks })
+            }
+
+            LockPolicy::ChargebackRatio { percent }
+                if u64::from(chargebacks) * 100 > u64::from(percent) * u64::from(deposits) =>
+            {
+                Some(LockReason::ChargebackRatio {
+                    chargebacks,
+                    deposits,
+                    percent,
+                })
+            }
+
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockReason::Chargebacks { count } => write!(f, "{count} chargebacks"),
+            LockReason::ChargebackRatio {
+                chargebacks,
+                deposits,
+                percent,
+            } => write!(
+                f,
+                "{chargebacks} chargebacks for {deposits} deposits is over {percent}%"
+            ),
+            LockReason::OpeningBalance => write!(f, "locked in opening balances"),
+        }
+    }
+}
+
+/// Limits how long after a deposit it can be disputed.
+/// A window with no limits set never closes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeWindow {
+    /// Maximum number of ledger entries between a deposit and its dispute
+    pub max_ledger_distance: Option<usize>,
+
+    /// Maximum number of seconds between a deposit and its dispute.
+    /// Only checked when both transactions have a timestamp.
+    pub max_age_secs: Option<u64>,
+
+    /// What to do with disputes that are still open when the window closes
+    pub on_expiry: DisputeExpiry,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeExpiry {
+    #[default]
+    Resolve,
+    ChargeBack,
+}
+
+impl DisputeWindow {
+    /// Returns true if a deposit at `deposit_idx` can no longer be disputed at `ledger_idx`
+    pub fn is_closed(
+        &self,
+        deposit_idx: usize,
+        deposit_timestamp: Option<u64>,
+        ledger_idx: usize,
+        timestamp: Option<u64>,
+    ) -> bool {
+        if let Some(max_distance) = self.max_ledger_distance {
+            if ledger_idx.saturating_sub(deposit_idx) > max_distance {
+                return true;
+            }
+        }
+
+        if let (Some(max_age), Some(deposit_ts), Some(ts)) =
+            (self.max_age_secs, deposit_timestamp, timestamp)
+        {
+            if ts.saturating_sub(deposit_ts) > max_age {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_after_chargebacks() {
+        let policy = LockPolicy::AfterChargebacks(2);
+
+        assert_eq!(policy.check(1, 5), None);
+        assert_eq!(
+            policy.check(2, 5),
+            Some(LockReason::Chargebacks { count: 2 })
+        );
+    }
+
+    #[test]
+    fn lock_after_chargeback_ratio() {
+        let policy = LockPolicy::ChargebackRatio { percent: 50 };
+
+        assert_eq!(policy.check(1, 2), None);
+        assert_eq!(
+            policy.check(2, 3),
+            Some(LockReason::ChargebackRatio {
+                chargebacks: 2,
+                deposits: 3,
+                percent: 50,
+            })
+        );
+    }
+
+    #[test]
+    fn never_lock() {
+        assert_eq!(LockPolicy::Never.check(u32::MAX, 0), None);
+    }
+
+    #[test]
+    fn unlimited_window_never_closes() {
+        let window = DisputeWindow::default();
+
+        assert!(!window.is_closed(0, Some(0), usize::MAX, Some(u64::MAX)));
+    }
+
+    #[test]
+    fn closes_after_ledger_distance() {
+        let window = DisputeWindow {
+            max_ledger_distance: Some(3),
+            ..DisputeWindow::default()
+        };
+
+        assert!(!window.is_closed(2, None, 5, None));
+        assert!(window.is_closed(2, None, 6, None));
+    }
+
+    #[test]
+    fn closes_after_max_age() {
+        let window = DisputeWindow {
+            max_age_secs: Some(100),
+            ..DisputeWindow::default()
+        };
+
+        assert!(!window.is_closed(0, Some(1000), 50, Some(1100)));
+        assert!(window.is_closed(0, Some(1000), 50, Some(1101)));
+    }
+
+    #[test]
+    fn ignores_max_age_without_timestamps() {
+        let window = DisputeWindow {
+            max_age_secs: Some(100),
+            ..DisputeWindow::default()
+        };
+
+        assert!(!window.is_closed(0, None, 50, Some(5000)));
+        assert!(!window.is_closed(0, Some(1000), 50, None));
+    }
+}
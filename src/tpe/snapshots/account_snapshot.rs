@@ -1,18 +1,41 @@
+use super::{
+    AccountPolicy, DebtPolicy, DisputeEvent, DisputeExpiry, DisputeState, LockReason, OpenDispute,
+};
+
 use crate::ids::{ClientId, TransactionId};
 use crate::Result;
 use crate::{AccountReport, Ledger, TransactionType};
 use crate::{Money, Transaction};
 
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Represents a snapshot in time for a given account
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AccountSnapshot {
     from_ledger_idx: Option<usize>,
     client_id: ClientId,
     available: Money,
     held: Money,
+    owed: Money,
     locked: bool,
+    lock_reason: Option<LockReason>,
+    flagged: bool,
+    chargebacks: u32,
+    policy: AccountPolicy,
+    deposits: BTreeMap<TransactionId, DepositRecord>,
+}
+
+/// Tracks the dispute state of a deposit made to this account
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct DepositRecord {
+    ledger_idx: usize,
+    timestamp: Option<u64>,
+    amount: Money,
+    state: DisputeState,
+    dispute_count: u32,
 }
 
 #[derive(Error, Debug)]
@@ -45,22 +68,188 @@ pub enum AccountTransactionError {
 
     #[error("Invalid charge back attempt: {0}")]
     InvalidChargeBack(String),
+
+    #[error("Dispute window expired: transaction {0} can no longer be disputed")]
+    DisputeWindowExpired(TransactionId),
+
+    #[error("Invalid write-off attempt: {0}")]
+    InvalidWriteOff(String),
+}
+
+impl AccountTransactionError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            AccountTransactionError::InvalidLedgerState(_) => "invalid_ledger_state",
+            AccountTransactionError::InvalidClientId(..) => "invalid_client_id",
+            AccountTransactionError::AccountLocked(..) => "account_locked",
+            AccountTransactionError::TransactionNotFound(_) => "transaction_not_found",
+            AccountTransactionError::InvalidDeposit(_) => "invalid_deposit",
+            AccountTransactionError::InvalidWithdrawal(_) => "invalid_withdrawal",
+            AccountTransactionError::InvalidDispute(_) => "invalid_dispute",
+            AccountTransactionError::InvalidResolve(_) => "invalid_resolve",
+            AccountTransactionError::InvalidChargeBack(_) => "invalid_charge_back",
+            AccountTransactionError::DisputeWindowExpired(_) => "dispute_window_expired",
+            AccountTransactionError::InvalidWriteOff(_) => "invalid_write_off",
+        }
+    }
+}
+
+/// Why an account report can't be used as an opening balance
+#[derive(Error, Debug)]
+pub enum OpeningBalanceError {
+    #[error("Invalid client in opening balances: {0:?}")]
+    InvalidClient(String),
+
+    #[error("Client {0} has more than one opening balance")]
+    DuplicateClient(ClientId),
+
+    #[error("Opening balance for client {client} has a total of {total}, but its balances add up to {expected}")]
+    TotalMismatch {
+        client: ClientId,
+        total: Money,
+        expected: Money,
+    },
+
+    #[error("Opening balance for client {0} has negative held or owed funds")]
+    NegativeBalance(ClientId),
+
+    #[error(
+        "Opening balance for client {0} owes funds, but the account policy doesn't track debt"
+    )]
+    UntrackedDebt(ClientId),
+}
+
+impl OpeningBalanceError {
+    /// A stable, machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            OpeningBalanceError::InvalidClient(_) => "invalid_client",
+            OpeningBalanceError::DuplicateClient(_) => "duplicate_client",
+            OpeningBalanceError::TotalMismatch { .. } => "total_mismatch",
+            OpeningBalanceError::NegativeBalance(_) => "negative_balance",
+            OpeningBalanceError::UntrackedDebt(_) => "untracked_debt",
+        }
+    }
 }
 
 impl AccountSnapshot {
     pub fn new(client_id: ClientId) -> Self {
+        Self::with_policy(client_id, AccountPolicy::default())
+    }
+
+    pub fn with_policy(client_id: ClientId, policy: AccountPolicy) -> Self {
         Self {
             from_ledger_idx: None,
             client_id,
             available: Money(0),
             held: Money(0),
+            owed: Money(0),
             locked: false,
+            lock_reason: None,
+            flagged: false,
+            chargebacks: 0,
+            policy,
+            deposits: BTreeMap::new(),
+        }
+    }
+
+    /// Starts an account from the balances in a report of an earlier run.
+    /// The total must equal available plus held, less anything owed.
+    ///
+    /// The deposits behind the balances aren't in this run's ledger, so they can't be disputed.
+    /// Funds that were already held stay held: no dispute, resolve or chargeback in this run can
+    /// release or remove them, and dispute windows never close on them.
+    pub fn from_report(report: &AccountReport, policy: AccountPolicy) -> Result<Self> {
+        let client_id = report
+            .client
+            .trim()
+            .parse()
+            .map(ClientId)
+            .map_err(|_| OpeningBalanceError::InvalidClient(report.client.clone()))?;
+
+        let parse = |amount: &str| Money::parse(amount.trim().to_string());
+
+        let available = parse(&report.available)?;
+        let held = parse(&report.held)?;
+        let total = parse(&report.total)?;
+        let owed = match &report.owed {
+            Some(owed) => parse(owed)?,
+            None => Money(0),
+        };
+
+        if held.0 < 0 || owed.0 < 0 {
+            Err(OpeningBalanceError::NegativeBalance(client_id))?;
+        }
+
+        if owed.0 > 0 && policy.debt == DebtPolicy::Disabled {
+            Err(OpeningBalanceError::UntrackedDebt(client_id))?;
+        }
+
+        let mut expected = available;
+        expected.add(&held)?;
+        expected.sub(&owed)?;
+
+        if expected != total {
+            Err(OpeningBalanceError::TotalMismatch {
+                client: client_id,
+                total,
+                expected,
+            })?;
         }
+
+        Ok(Self {
+            available,
+            held,
+            owed,
+            locked: report.locked,
+            lock_reason: report.locked.then_some(LockReason::OpeningBalance),
+            flagged: report.flagged.unwrap_or(false),
+            ..Self::with_policy(client_id, policy)
+        })
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    pub fn available(&self) -> Money {
+        self.available
+    }
+
+    pub fn held(&self) -> Money {
+        self.held
+    }
+
+    pub fn owed(&self) -> Money {
+        self.owed
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
     }
 
     pub fn parse_report(&self) -> Result<AccountReport> {
         let mut total = self.available;
         total.add(&self.held)?;
+        total.sub(&self.owed)?;
+
+        let owed = match self.policy.debt {
+            DebtPolicy::Disabled => None,
+            DebtPolicy::Track => Some(self.owed.to_string()),
+        };
+
+        let (flagged, lock_reason) = match self.policy.lock {
+            None => (None, None),
+            Some(_) => {
+                let lock_reason = self
+                    .lock_reason
+                    .map(|reason| reason.to_string())
+                    .unwrap_or_default();
+
+                (Some(self.flagged), Some(lock_reason))
+            }
+        };
 
         Ok(AccountReport {
             client: self.client_id.to_string(),
@@ -68,9 +257,34 @@ impl AccountSnapshot {
             held: self.held.to_string(),
             total: total.to_string(),
             locked: self.locked,
+            owed,
+            flagged,
+            lock_reason,
         })
     }
 
+    /// Returns why the account was locked, if it has been
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        self.lock_reason
+    }
+
+    /// Returns the dispute state of a deposit made to this account
+    pub fn dispute_state(&self, tx_id: &TransactionId) -> Option<DisputeState> {
+        self.deposits.get(tx_id).map(|deposit| deposit.state)
+    }
+
+    /// Returns every dispute that is currently holding funds, ordered by transaction ID
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        self.deposits
+            .iter()
+            .filter(|(_, deposit)| deposit.state == DisputeState::Disputed)
+            .map(|(&tx_id, deposit)| OpenDispute {
+                tx_id,
+                amount: deposit.amount,
+            })
+            .collect()
+    }
+
     /// Attempts to apply any new transactions that have been added to the ledger since the last
     /// time this was run.
     ///
@@ -100,6 +314,17 @@ impl AccountSnapshot {
         Ok(())
     }
 
+    /// Applies a single ledger entry, as if every earlier entry for the client has been applied.
+    /// Unlike `apply_transactions`, a failed entry isn't invalidated, so past balances can be
+    /// rebuilt from a ledger that's already been processed.
+    pub fn replay(&mut self, ledger: &Ledger, ledger_idx: usize) -> Result {
+        let res = self.apply_transaction(ledger, &ledger_idx);
+
+        self.from_ledger_idx = Some(ledger_idx);
+
+        res
+    }
+
     fn apply_transaction(&mut self, ledger: &Ledger, ledger_idx: &usize) -> Result {
         let tx = ledger.get_by_index(ledger_idx).ok_or_else(|| {
             AccountTransactionError::TransactionNotFound(format!(
@@ -107,29 +332,63 @@ impl AccountSnapshot {
             ))
         })?;
 
-        if self.locked {
+        self.expire_disputes(*ledger_idx, tx.timestamp)?;
+
+        // Write-offs are administrative, so they're still allowed on locked accounts
+        if self.locked && tx.tx_type != TransactionType::WriteOff {
             Err(AccountTransactionError::AccountLocked(
                 self.client_id,
                 tx.id,
             ))?;
         }
 
-        let mut transactions = ledger.get_valid_transactions_until(ledger_idx, &tx.id);
-
-        // already referencing latest transaction as `tx`
-        transactions.pop();
-
         match tx.tx_type {
             TransactionType::Deposit { amount } => {
-                self.apply_deposit(&mut transactions, tx, amount)?
+                self.apply_deposit(ledger, ledger_idx, tx, amount)?
             }
             TransactionType::Withdrawal { amount } => {
-                self.apply_withdrawal(&mut transactions, tx, amount)?
+                self.apply_withdrawal(ledger, ledger_idx, tx, amount)?
             }
 
-            TransactionType::Dispute => self.apply_dispute(&mut transactions, tx)?,
-            TransactionType::Resolve => self.apply_resolve(&mut transactions, tx)?,
-            TransactionType::ChargeBack => self.apply_charge_back(&mut transactions, tx)?,
+            TransactionType::Dispute => self.apply_dispute(ledger, ledger_idx, tx)?,
+            TransactionType::Resolve => self.apply_resolve(ledger, ledger_idx, tx)?,
+            TransactionType::ChargeBack => self.apply_charge_back(ledger, ledger_idx, tx)?,
+            TransactionType::WriteOff => self.apply_write_off(ledger, ledger_idx, tx)?,
+        }
+
+        Ok(())
+    }
+
+    /// Closes any open disputes whose dispute window has ended as of the given ledger index and
+    /// timestamp, resolving or charging them back according to the account's policy.
+    pub fn expire_disputes(&mut self, ledger_idx: usize, timestamp: Option<u64>) -> Result {
+        let window = self.policy.dispute_window;
+
+        let expired: Vec<TransactionId> = self
+            .deposits
+            .iter()
+            .filter(|(_, deposit)| {
+                deposit.state == DisputeState::Disputed
+                    && window.is_closed(
+                        deposit.ledger_idx,
+                        deposit.timestamp,
+                        ledger_idx,
+                        timestamp,
+                    )
+            })
+            .map(|(&tx_id, _)| tx_id)
+            .collect();
+
+        for tx_id in expired {
+            log::debug!(
+                "Dispute window closed for transaction {tx_id}, applying {:?}",
+                window.on_expiry
+            );
+
+            match window.on_expiry {
+                DisputeExpiry::Resolve => self.resolve_deposit(&tx_id)?,
+                DisputeExpiry::ChargeBack => self.charge_back_deposit(&tx_id)?,
+            }
         }
 
         Ok(())
@@ -137,11 +396,12 @@ impl AccountSnapshot {
 
     fn apply_deposit(
         &mut self,
-        transactions: &mut Vec<&Transaction>,
+        ledger: &Ledger,
+        ledger_idx: &usize,
         tx: &Transaction,
         amount: Money,
     ) -> Result {
-        if !transactions.is_empty() {
+        if self.is_duplicate(ledger, ledger_idx, &tx.id) {
             Err(AccountTransactionError::InvalidDeposit(format!(
                 "Duplicate transaction ID found: {}",
                 tx.id
@@ -149,17 +409,30 @@ impl AccountSnapshot {
         }
 
         self.available.add(&amount)?;
+        self.pay_down_debt()?;
+
+        self.deposits.insert(
+            tx.id,
+            DepositRecord {
+                ledger_idx: *ledger_idx,
+                timestamp: tx.timestamp,
+                amount,
+                state: DisputeState::Undisputed,
+                dispute_count: 0,
+            },
+        );
 
         Ok(())
     }
 
     fn apply_withdrawal(
         &mut self,
-        transactions: &mut Vec<&Transaction>,
+        ledger: &Ledger,
+        ledger_idx: &usize,
         tx: &Transaction,
         amount: Money,
     ) -> Result {
-        if !transactions.is_empty() {
+        if self.is_duplicate(ledger, ledger_idx, &tx.id) {
             Err(AccountTransactionError::InvalidWithdrawal(format!(
                 "Duplicate transaction ID found: {}",
                 tx.id
@@ -178,169 +451,274 @@ impl AccountSnapshot {
         Ok(())
     }
 
-    fn apply_dispute(&mut self, transactions: &mut Vec<&Transaction>, tx: &Transaction) -> Result {
-        let og = self.get_expected_original(
-            transactions,
-            &tx.id,
-            &AccountTransactionError::InvalidDispute,
-        )?;
+    fn apply_dispute(&mut self, ledger: &Ledger, ledger_idx: &usize, tx: &Transaction) -> Result {
+        let err_gen = &AccountTransactionError::InvalidDispute;
 
-        match og.tx_type {
-            TransactionType::Deposit { amount } => {
-                let mut available = self.available;
-                let mut held = self.held;
+        let deposit = self.get_expected_deposit(ledger, ledger_idx, &tx.id, err_gen)?;
+        let next_state = self.get_next_state(deposit, &tx.id, DisputeEvent::Dispute, err_gen)?;
 
-                available.sub(&amount)?;
-                held.add(&amount)?;
+        if self.policy.dispute_window.is_closed(
+            deposit.ledger_idx,
+            deposit.timestamp,
+            *ledger_idx,
+            tx.timestamp,
+        ) {
+            Err(AccountTransactionError::DisputeWindowExpired(tx.id))?;
+        }
 
-                // Only apply if both operations were successful
-                self.available = available;
-                self.held = held;
-            }
+        let amount = deposit.amount;
 
-            // Not sure if possible to dispute withdrawals.
-            // Assuming that you cannot, based on the term "ChargeBack"
+        self.hold(&amount)?;
 
-            // TransactionType::Withdrawal { amount } => { .. },
-            _ => Err(AccountTransactionError::InvalidDispute(format!(
-                "Cannot dispute a transaction of type: {:?}",
-                og.tx_type
-            )))?,
-        }
+        let deposit = self.get_deposit_mut(&tx.id)?;
+        deposit.state = next_state;
+        deposit.dispute_count += 1;
 
         Ok(())
     }
 
-    fn apply_resolve(&mut self, transactions: &mut Vec<&Transaction>, tx: &Transaction) -> Result {
-        let prev = self.get_expected_prev(
-            transactions,
-            &tx.id,
-            &AccountTransactionError::InvalidResolve,
-        )?;
+    fn apply_resolve(&mut self, ledger: &Ledger, ledger_idx: &usize, tx: &Transaction) -> Result {
+        let err_gen = &AccountTransactionError::InvalidResolve;
 
-        let og = match prev.tx_type {
-            TransactionType::Dispute => self.get_expected_original(
-                transactions,
-                &tx.id,
-                &AccountTransactionError::InvalidResolve,
-            )?,
+        let deposit = self.get_expected_deposit(ledger, ledger_idx, &tx.id, err_gen)?;
+        self.get_next_state(deposit, &tx.id, DisputeEvent::Resolve, err_gen)?;
 
-            _ => Err(AccountTransactionError::InvalidResolve(format!(
-                "Cannot resolve a transaction of type: {:?}",
-                prev.tx_type
-            )))?,
-        };
+        self.resolve_deposit(&tx.id)
+    }
 
-        match og.tx_type {
-            TransactionType::Deposit { amount } => {
-                let mut available = self.available;
-                let mut held = self.held;
+    fn apply_charge_back(
+        &mut self,
+        ledger: &Ledger,
+        ledger_idx: &usize,
+        tx: &Transaction,
+    ) -> Result {
+        let err_gen = &AccountTransactionError::InvalidChargeBack;
 
-                held.sub(&amount)?;
-                available.add(&amount)?;
+        let deposit = self.get_expected_deposit(ledger, ledger_idx, &tx.id, err_gen)?;
+        self.get_next_state(deposit, &tx.id, DisputeEvent::ChargeBack, err_gen)?;
 
-                // Only apply if both operations were successful
-                self.available = available;
-                self.held = held;
-            }
-            _ => Err(AccountTransactionError::InvalidLedgerState(format!(
-                "Cannot find deposit to resolve for transaction ID: {}",
-                tx.id,
-            )))?,
+        self.charge_back_deposit(&tx.id)
+    }
+
+    fn apply_write_off(&mut self, ledger: &Ledger, ledger_idx: &usize, tx: &Transaction) -> Result {
+        if self.is_duplicate(ledger, ledger_idx, &tx.id) {
+            Err(AccountTransactionError::InvalidWriteOff(format!(
+                "Duplicate transaction ID found: {}",
+                tx.id
+            )))?;
+        }
+
+        if self.owed.0 <= 0 {
+            Err(AccountTransactionError::InvalidWriteOff(format!(
+                "Client {} has no debt to write off",
+                self.client_id
+            )))?;
         }
 
+        log::debug!(
+            "Writing off {} owed by client {}",
+            self.owed,
+            self.client_id
+        );
+
+        self.owed = Money(0);
+
         Ok(())
     }
 
-    fn apply_charge_back(
-        &mut self,
-        transactions: &mut Vec<&Transaction>,
-        tx: &Transaction,
-    ) -> Result {
-        let prev = self.get_expected_prev(
-            transactions,
-            &tx.id,
-            &AccountTransactionError::InvalidChargeBack,
-        )?;
-
-        let og = match prev.tx_type {
-            TransactionType::Dispute => self.get_expected_original(
-                transactions,
-                &tx.id,
-                &AccountTransactionError::InvalidChargeBack,
-            )?,
-
-            _ => Err(AccountTransactionError::InvalidChargeBack(format!(
-                "Cannot charge back a transaction of type: {:?}",
-                prev.tx_type
-            )))?,
-        };
+    /// Releases the held funds of a disputed deposit
+    fn resolve_deposit(&mut self, tx_id: &TransactionId) -> Result {
+        let amount = self.get_disputed_amount(tx_id)?;
+
+        self.release_held(&amount)?;
+
+        self.get_deposit_mut(tx_id)?.state = DisputeState::Resolved;
+
+        Ok(())
+    }
+
+    /// Removes the held funds of a disputed deposit, and locks or flags the account according to
+    /// the lock policy
+    fn charge_back_deposit(&mut self, tx_id: &TransactionId) -> Result {
+        let amount = self.get_disputed_amount(tx_id)?;
+
+        self.held.sub(&amount)?;
+
+        self.get_deposit_mut(tx_id)?.state = DisputeState::ChargedBack;
+        self.chargebacks += 1;
+
+        let deposits = self.deposits.len() as u32;
+
+        let lock = self.policy.lock.unwrap_or_default();
+
+        match lock.check(self.chargebacks, deposits) {
+            Some(reason) if !self.locked => {
+                log::debug!("Locking account for client {}: {reason}", self.client_id);
 
-        match og.tx_type {
-            TransactionType::Deposit { amount } => {
-                self.held.sub(&amount)?;
                 self.locked = true;
+                self.lock_reason = Some(reason);
             }
+            Some(_) => {}
+            None => self.flagged = true,
+        }
+
+        Ok(())
+    }
+
+    /// Moves funds from available to held
+    fn hold(&mut self, amount: &Money) -> Result {
+        let mut available = self.available;
+        let mut held = self.held;
+
+        let mut owed = self.owed;
+
+        available.sub(amount)?;
+        held.add(amount)?;
+
+        if self.policy.debt == DebtPolicy::Track && available.0 < 0 {
+            owed.sub(&available)?;
+            available = Money(0);
+        }
+
+        // Only apply if all operations were successful
+        self.available = available;
+        self.held = held;
+        self.owed = owed;
+
+        Ok(())
+    }
+
+    /// Moves funds from held back to available
+    fn release_held(&mut self, amount: &Money) -> Result {
+        let mut available = self.available;
+        let mut held = self.held;
+
+        held.sub(amount)?;
+        available.add(amount)?;
+
+        // Only apply if both operations were successful
+        self.available = available;
+        self.held = held;
+
+        self.pay_down_debt()
+    }
+
+    /// Uses available funds to pay down as much owed debt as possible
+    fn pay_down_debt(&mut self) -> Result {
+        if self.owed.0 <= 0 || self.available.0 <= 0 {
+            return Ok(());
+        }
+
+        let payment = Money(self.owed.0.min(self.available.0));
+
+        self.available.sub(&payment)?;
+        self.owed.sub(&payment)?;
+
+        Ok(())
+    }
+
+    /// Returns true if a valid transaction with the same ID came before the given ledger index
+    fn is_duplicate(&self, ledger: &Ledger, ledger_idx: &usize, tx_id: &TransactionId) -> bool {
+        ledger
+            .get_first_valid_index(tx_id)
+            .map(|first_idx| first_idx < *ledger_idx)
+            .unwrap_or(false)
+    }
+
+    /// Gets the amount held by a disputed deposit
+    /// Fails if the deposit isn't currently disputed
+    fn get_disputed_amount(&self, tx_id: &TransactionId) -> Result<Money> {
+        match self.deposits.get(tx_id) {
+            Some(deposit) if deposit.state == DisputeState::Disputed => Ok(deposit.amount),
+
             _ => Err(AccountTransactionError::InvalidLedgerState(format!(
-                "Cannot find deposit to charge back for transaction ID: {}",
-                tx.id,
+                "No disputed deposit found for transaction ID: {tx_id}"
             )))?,
         }
+    }
 
-        Ok(())
+    /// Gets a deposit to update, once it's been checked by `get_expected_deposit`
+    /// Fails if the deposit doesn't exist
+    fn get_deposit_mut(&mut self, tx_id: &TransactionId) -> Result<&mut DepositRecord> {
+        let deposit = self.deposits.get_mut(tx_id).ok_or_else(|| {
+            AccountTransactionError::InvalidLedgerState(format!(
+                "No deposit found for transaction ID: {tx_id}"
+            ))
+        })?;
+
+        Ok(deposit)
     }
 
-    /// Gets the original transaction
-    /// Fails if no previous transactions, different client_ids, or invalid internal state
-    fn get_expected_original<'a>(
+    /// Gets the state a deposit moves to for the given dispute event
+    /// Fails if the transition isn't allowed
+    fn get_next_state(
         &self,
-        transactions: &mut Vec<&'a Transaction>,
+        deposit: &DepositRecord,
         tx_id: &TransactionId,
+        event: DisputeEvent,
         err_gen: &dyn Fn(String) -> AccountTransactionError,
-    ) -> Result<&'a Transaction> {
-        let mut prev = self.get_expected_prev(transactions, tx_id, err_gen)?;
-
-        while prev.tx_type == TransactionType::Resolve {
-            transactions.pop();
-
-            prev = transactions.pop().ok_or_else(|| {
-                AccountTransactionError::InvalidLedgerState(format!(
-                    "No previous transaction found with ID: {}",
-                    tx_id
+    ) -> Result<DisputeState> {
+        let next_state = deposit
+            .state
+            .transition(event, deposit.dispute_count, self.policy.max_redisputes)
+            .ok_or_else(|| {
+                err_gen(format!(
+                    "Cannot apply {event:?} to transaction {tx_id} while it is {}",
+                    deposit.state
                 ))
             })?;
-        }
 
-        Ok(prev)
+        Ok(next_state)
     }
 
-    /// Gets the previous transaction, ensuring the same client_id
-    /// Fails if no previous transaction or different client_ids
-    fn get_expected_prev<'a>(
+    /// Gets the deposit a dispute, resolve, or charge back refers to
+    /// Fails if no such deposit exists for this client
+    fn get_expected_deposit(
         &self,
-        transactions: &mut Vec<&'a Transaction>,
+        ledger: &Ledger,
+        ledger_idx: &usize,
         tx_id: &TransactionId,
         err_gen: &dyn Fn(String) -> AccountTransactionError,
-    ) -> Result<&'a Transaction> {
-        let prev = transactions
-            .pop()
+    ) -> Result<&DepositRecord> {
+        if let Some(deposit) = self.deposits.get(tx_id) {
+            return Ok(deposit);
+        }
+
+        // Not one of our deposits, so use the ledger to explain why
+        let og = ledger
+            .get_first_valid_index(tx_id)
+            .filter(|idx| idx < ledger_idx)
+            .and_then(|idx| ledger.get_by_index(&idx))
             .ok_or_else(|| err_gen(format!("No previous transaction found with ID: {}", tx_id)))?;
 
-        if prev.client_id != self.client_id {
+        if og.client_id != self.client_id {
             Err(AccountTransactionError::InvalidClientId(
-                prev.id,
-                prev.client_id,
+                og.id,
+                og.client_id,
                 self.client_id,
             ))?;
         }
 
-        Ok(prev)
+        match og.tx_type {
+            // A valid deposit for this client should always have been recorded
+            TransactionType::Deposit { .. } => Err(AccountTransactionError::InvalidLedgerState(
+                format!("Cannot find deposit for transaction ID: {}", tx_id),
+            ))?,
+
+            // Not sure if possible to dispute withdrawals.
+            // Assuming that you cannot, based on the term "ChargeBack".
+            // Anything else, like a write-off or another dispute, only ever refers to a deposit.
+            _ => Err(err_gen(format!(
+                "Cannot dispute a transaction of type: {:?}",
+                og.tx_type
+            )))?,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ids::TransactionId, Money, Transaction};
+    use crate::{ids::TransactionId, DebtPolicy, DisputeWindow, LockPolicy, Money, Transaction};
 
     use super::*;
 
@@ -362,10 +740,32 @@ mod tests {
             id,
             client_id,
             tx_type,
+            timestamp: None,
             invalid: false,
         }
     }
 
+    fn build_deposit(
+        ledger_idx: usize,
+        amount: Money,
+        state: DisputeState,
+        dispute_count: u32,
+    ) -> DepositRecord {
+        DepositRecord {
+            ledger_idx,
+            timestamp: None,
+            amount,
+            state,
+            dispute_count,
+        }
+    }
+
+    fn build_deposits(
+        deposits: Vec<(TransactionId, DepositRecord)>,
+    ) -> BTreeMap<TransactionId, DepositRecord> {
+        deposits.into_iter().collect()
+    }
+
     fn build_ledger(transactions: Vec<Transaction>) -> Ledger {
         let mut ledger = Ledger::new();
 
@@ -413,6 +813,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
         assert_eq!(
@@ -423,6 +828,11 @@ mod tests {
                 available: OTHER_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    OTHER_TRANSACTION_ID,
+                    build_deposit(1, OTHER_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(OTHER_CLIENT_ID)
             }
         );
     }
@@ -460,6 +870,11 @@ mod tests {
                 available: Money(0),
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -495,6 +910,11 @@ mod tests {
                 available: Money(0),
                 held: SOME_AMOUNT,
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Disputed, 1)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -536,6 +956,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Resolved, 1)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -577,6 +1002,13 @@ mod tests {
                 available: Money(0),
                 held: Money(0),
                 locked: true,
+                lock_reason: Some(LockReason::Chargebacks { count: 1 }),
+                chargebacks: 1,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::ChargedBack, 1)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -621,6 +1053,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -663,6 +1100,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -716,6 +1158,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, deposit_amount, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -762,6 +1209,11 @@ mod tests {
                 available: Money(0),
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
@@ -807,6 +1259,11 @@ mod tests {
                 available: SOME_AMOUNT,
                 held: Money(0),
                 locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Undisputed, 0)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
 
@@ -818,6 +1275,7 @@ mod tests {
                 available: Money(0),
                 held: Money(0),
                 locked: false,
+                ..AccountSnapshot::new(OTHER_CLIENT_ID)
             }
         );
     }
@@ -871,7 +1329,811 @@ mod tests {
                 available: Money(0),
                 held: Money(0),
                 locked: true,
+                lock_reason: Some(LockReason::Chargebacks { count: 1 }),
+                chargebacks: 1,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::ChargedBack, 1)
+                )]),
+                ..AccountSnapshot::new(SOME_CLIENT_ID)
             }
         );
     }
+
+    fn build_windowed_snapshot(on_expiry: DisputeExpiry) -> AccountSnapshot {
+        let policy = AccountPolicy {
+            dispute_window: DisputeWindow {
+                max_ledger_distance: Some(2),
+                max_age_secs: Some(100),
+                on_expiry,
+            },
+            ..AccountPolicy::default()
+        };
+
+        AccountSnapshot::with_policy(SOME_CLIENT_ID, policy)
+    }
+
+    fn build_filler_deposit(id: u32) -> Transaction {
+        build_transaction(
+            TransactionId(id),
+            OTHER_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: OTHER_AMOUNT,
+            },
+        )
+    }
+
+    #[test]
+    fn fail_to_dispute_outside_ledger_window() {
+        let mut snapshot = build_windowed_snapshot(DisputeExpiry::Resolve);
+
+        let transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+
+        let transaction2 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+
+        let mut ledger = build_ledger(vec![
+            transaction1,
+            build_filler_deposit(1),
+            build_filler_deposit(2),
+            transaction2,
+        ]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        let err = res.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AccountTransactionError>(),
+            Some(AccountTransactionError::DisputeWindowExpired(
+                SOME_TRANSACTION_ID
+            ))
+        ));
+
+        assert_eq!(snapshot.available, SOME_AMOUNT);
+        assert_eq!(snapshot.held, Money(0));
+        assert!(snapshot.open_disputes().is_empty());
+    }
+
+    #[test]
+    fn fail_to_dispute_outside_time_window() {
+        let mut snapshot = build_windowed_snapshot(DisputeExpiry::Resolve);
+
+        let mut transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+        transaction1.timestamp = Some(1000);
+
+        let mut transaction2 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+        transaction2.timestamp = Some(1101);
+
+        let mut ledger = build_ledger(vec![transaction1, transaction2]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+
+        assert_eq!(snapshot.available, SOME_AMOUNT);
+        assert_eq!(snapshot.held, Money(0));
+    }
+
+    #[test]
+    fn resolve_dispute_when_window_expires() {
+        let mut snapshot = build_windowed_snapshot(DisputeExpiry::Resolve);
+
+        let transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+
+        let transaction2 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+
+        let mut ledger = build_ledger(vec![transaction1, transaction2]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+        assert_eq!(snapshot.held, SOME_AMOUNT);
+
+        let res = snapshot.expire_disputes(2, None);
+        assert!(res.is_ok());
+        assert_eq!(snapshot.held, SOME_AMOUNT);
+
+        let res = snapshot.expire_disputes(3, None);
+        assert!(res.is_ok());
+
+        assert_eq!(
+            snapshot,
+            AccountSnapshot {
+                client_id: SOME_CLIENT_ID,
+                from_ledger_idx: Some(1),
+                available: SOME_AMOUNT,
+                held: Money(0),
+                locked: false,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    build_deposit(0, SOME_AMOUNT, DisputeState::Resolved, 1)
+                )]),
+                ..build_windowed_snapshot(DisputeExpiry::Resolve)
+            }
+        );
+    }
+
+    #[test]
+    fn charge_back_dispute_when_window_expires() {
+        let mut snapshot = build_windowed_snapshot(DisputeExpiry::ChargeBack);
+
+        let mut transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+        transaction1.timestamp = Some(1000);
+
+        let mut transaction2 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+        transaction2.timestamp = Some(1050);
+
+        let mut transaction3 = build_transaction(
+            OTHER_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: OTHER_AMOUNT,
+            },
+        );
+        transaction3.timestamp = Some(1200);
+
+        let mut ledger = build_ledger(vec![transaction1, transaction2, transaction3]);
+
+        // The dispute is charged back before the deposit, which then fails on the locked account
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+
+        assert_eq!(
+            snapshot,
+            AccountSnapshot {
+                client_id: SOME_CLIENT_ID,
+                from_ledger_idx: Some(2),
+                available: Money(0),
+                held: Money(0),
+                locked: true,
+                lock_reason: Some(LockReason::Chargebacks { count: 1 }),
+                chargebacks: 1,
+                deposits: build_deposits(vec![(
+                    SOME_TRANSACTION_ID,
+                    DepositRecord {
+                        timestamp: Some(1000),
+                        ..build_deposit(0, SOME_AMOUNT, DisputeState::ChargedBack, 1)
+                    }
+                )]),
+                ..build_windowed_snapshot(DisputeExpiry::ChargeBack)
+            }
+        );
+    }
+
+    #[test]
+    fn fail_to_resolve_expired_dispute() {
+        let mut snapshot = build_windowed_snapshot(DisputeExpiry::Resolve);
+
+        let transaction1 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: SOME_AMOUNT,
+            },
+        );
+
+        let transaction2 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        );
+
+        let transaction3 = build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Resolve,
+        );
+
+        let mut ledger = build_ledger(vec![
+            transaction1,
+            transaction2,
+            build_filler_deposit(1),
+            build_filler_deposit(2),
+            transaction3,
+        ]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+
+        assert_eq!(snapshot.available, SOME_AMOUNT);
+        assert_eq!(snapshot.held, Money(0));
+        assert!(snapshot.open_disputes().is_empty());
+    }
+
+    fn build_dispute_ledger(tx_types: Vec<TransactionType>) -> Ledger {
+        let mut transactions = vec![
+            build_transaction(
+                SOME_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            ),
+            build_transaction(
+                OTHER_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: OTHER_AMOUNT,
+                },
+            ),
+        ];
+
+        for tx_type in tx_types {
+            transactions.push(build_transaction(
+                SOME_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                tx_type,
+            ));
+        }
+
+        build_ledger(transactions)
+    }
+
+    #[test]
+    fn redispute_after_resolve() {
+        let mut snapshot = AccountSnapshot::new(SOME_CLIENT_ID);
+
+        let mut ledger = build_dispute_ledger(vec![
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+        ]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(
+            snapshot.dispute_state(&SOME_TRANSACTION_ID),
+            Some(DisputeState::Disputed)
+        );
+        assert_eq!(snapshot.deposits[&SOME_TRANSACTION_ID].dispute_count, 2);
+        assert_eq!(snapshot.held, SOME_AMOUNT);
+    }
+
+    #[test]
+    fn fail_to_redispute_past_policy_limit() {
+        let policy = AccountPolicy {
+            max_redisputes: Some(0),
+            ..AccountPolicy::default()
+        };
+        let mut snapshot = AccountSnapshot::with_policy(SOME_CLIENT_ID, policy);
+
+        let mut ledger = build_dispute_ledger(vec![
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+        ]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+
+        assert_eq!(
+            snapshot.dispute_state(&SOME_TRANSACTION_ID),
+            Some(DisputeState::Resolved)
+        );
+        assert_eq!(snapshot.held, Money(0));
+    }
+
+    #[test]
+    fn fail_to_resolve_twice() {
+        let mut snapshot = AccountSnapshot::new(SOME_CLIENT_ID);
+
+        let mut ledger = build_dispute_ledger(vec![
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Resolve,
+        ]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+
+        let mut expected_available = SOME_AMOUNT;
+        expected_available.add(&OTHER_AMOUNT).unwrap();
+
+        assert_eq!(snapshot.available, expected_available);
+        assert_eq!(snapshot.held, Money(0));
+    }
+
+    #[test]
+    fn open_disputes() {
+        let mut snapshot = AccountSnapshot::new(SOME_CLIENT_ID);
+
+        let mut ledger = build_dispute_ledger(vec![TransactionType::Dispute]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        ledger.append(build_transaction(
+            OTHER_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(
+            snapshot.open_disputes(),
+            vec![
+                OpenDispute {
+                    tx_id: SOME_TRANSACTION_ID,
+                    amount: SOME_AMOUNT,
+                },
+                OpenDispute {
+                    tx_id: OTHER_TRANSACTION_ID,
+                    amount: OTHER_AMOUNT,
+                },
+            ]
+        );
+
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Resolve,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(
+            snapshot.open_disputes(),
+            vec![OpenDispute {
+                tx_id: OTHER_TRANSACTION_ID,
+                amount: OTHER_AMOUNT,
+            }]
+        );
+    }
+
+    fn build_debt_snapshot() -> AccountSnapshot {
+        let policy = AccountPolicy {
+            debt: DebtPolicy::Track,
+            ..AccountPolicy::default()
+        };
+
+        AccountSnapshot::with_policy(SOME_CLIENT_ID, policy)
+    }
+
+    /// Tracks debt without locking, so the account stays open after its charge back
+    fn build_open_debt_snapshot() -> AccountSnapshot {
+        let policy = AccountPolicy {
+            debt: DebtPolicy::Track,
+            lock: Some(LockPolicy::Never),
+            ..AccountPolicy::default()
+        };
+
+        AccountSnapshot::with_policy(SOME_CLIENT_ID, policy)
+    }
+
+    fn build_debt_ledger() -> Ledger {
+        build_ledger(vec![
+            build_transaction(
+                SOME_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            ),
+            build_transaction(
+                OTHER_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: SOME_AMOUNT,
+                },
+            ),
+            build_transaction(
+                SOME_TRANSACTION_ID,
+                SOME_CLIENT_ID,
+                TransactionType::Dispute,
+            ),
+        ])
+    }
+
+    #[test]
+    fn dispute_without_debt_tracking_goes_negative() {
+        let mut snapshot = AccountSnapshot::new(SOME_CLIENT_ID);
+        let mut ledger = build_debt_ledger();
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(snapshot.available, Money(-SOME_AMOUNT.0));
+        assert_eq!(snapshot.held, SOME_AMOUNT);
+        assert_eq!(snapshot.owed, Money(0));
+        assert_eq!(snapshot.parse_report().unwrap().owed, None);
+    }
+
+    #[test]
+    fn dispute_with_debt_tracking_becomes_owed() {
+        let mut snapshot = build_debt_snapshot();
+        let mut ledger = build_debt_ledger();
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(snapshot.available, Money(0));
+        assert_eq!(snapshot.held, SOME_AMOUNT);
+        assert_eq!(snapshot.owed, SOME_AMOUNT);
+
+        let report = snapshot.parse_report().unwrap();
+        assert_eq!(report.total, Money(0).to_string());
+        assert_eq!(report.owed, Some(SOME_AMOUNT.to_string()));
+    }
+
+    #[test]
+    fn resolve_pays_down_debt() {
+        let mut snapshot = build_debt_snapshot();
+        let mut ledger = build_debt_ledger();
+
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Resolve,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(snapshot.available, Money(0));
+        assert_eq!(snapshot.held, Money(0));
+        assert_eq!(snapshot.owed, Money(0));
+    }
+
+    #[test]
+    fn deposit_pays_down_debt_after_charge_back() {
+        let mut snapshot = build_open_debt_snapshot();
+        let mut ledger = build_debt_ledger();
+
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::ChargeBack,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert_eq!(snapshot.held, Money(0));
+        assert_eq!(snapshot.owed, SOME_AMOUNT);
+        assert_eq!(
+            snapshot.parse_report().unwrap().total,
+            Money(-SOME_AMOUNT.0).to_string()
+        );
+        assert!(!snapshot.is_locked());
+
+        ledger.append(build_transaction(
+            TransactionId(999),
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: OTHER_AMOUNT,
+            },
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        let mut expected_owed = SOME_AMOUNT;
+        expected_owed.sub(&OTHER_AMOUNT).unwrap();
+
+        assert_eq!(snapshot.available, Money(0));
+        assert_eq!(snapshot.owed, expected_owed);
+    }
+
+    #[test]
+    fn write_off_debt_on_locked_account() {
+        let mut snapshot = build_debt_snapshot();
+        let mut ledger = build_debt_ledger();
+
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::ChargeBack,
+        ));
+        ledger.append(build_transaction(
+            TransactionId(999),
+            SOME_CLIENT_ID,
+            TransactionType::WriteOff,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert!(snapshot.locked);
+        assert_eq!(snapshot.owed, Money(0));
+        assert_eq!(snapshot.parse_report().unwrap().total, Money(0).to_string());
+    }
+
+    #[test]
+    fn fail_to_dispute_write_off() {
+        let mut snapshot = build_open_debt_snapshot();
+        let mut ledger = build_debt_ledger();
+
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::ChargeBack,
+        ));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        // Still open, so the dispute is checked against the write-off, rather than the lock
+        assert!(!snapshot.is_locked());
+
+        let write_off_id = TransactionId(999);
+
+        ledger.append(build_transaction(
+            write_off_id,
+            SOME_CLIENT_ID,
+            TransactionType::WriteOff,
+        ));
+        ledger.append(build_transaction(
+            write_off_id,
+            SOME_CLIENT_ID,
+            TransactionType::Dispute,
+        ));
+
+        let err = snapshot.apply_transactions(&mut ledger).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<AccountTransactionError>(),
+            Some(AccountTransactionError::InvalidDispute(_))
+        ));
+        assert_eq!(snapshot.owed, Money(0));
+        assert_eq!(snapshot.held, Money(0));
+    }
+
+    #[test]
+    fn fail_to_write_off_without_debt() {
+        let mut snapshot = build_debt_snapshot();
+
+        let mut ledger = build_ledger(vec![build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::WriteOff,
+        )]);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_err());
+    }
+
+    fn build_charge_back_ledger(deposit_count: u32, charge_back_count: u32) -> Ledger {
+        let mut transactions = vec![];
+
+        for id in 0..deposit_count {
+            transactions.push(build_transaction(
+                TransactionId(id),
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            ));
+        }
+
+        for id in 0..charge_back_count {
+            for tx_type in [TransactionType::Dispute, TransactionType::ChargeBack] {
+                transactions.push(build_transaction(
+                    TransactionId(id),
+                    SOME_CLIENT_ID,
+                    tx_type,
+                ));
+            }
+        }
+
+        build_ledger(transactions)
+    }
+
+    fn build_lock_snapshot(lock: LockPolicy) -> AccountSnapshot {
+        let policy = AccountPolicy {
+            lock: Some(lock),
+            ..AccountPolicy::default()
+        };
+
+        AccountSnapshot::with_policy(SOME_CLIENT_ID, policy)
+    }
+
+    #[test]
+    fn report_lock_columns_for_explicit_default_policy() {
+        let report = AccountSnapshot::new(SOME_CLIENT_ID).parse_report().unwrap();
+        assert_eq!(report.flagged, None);
+        assert_eq!(report.lock_reason, None);
+
+        let mut snapshot = build_lock_snapshot(LockPolicy::default());
+        let mut ledger = build_charge_back_ledger(1, 1);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+        assert!(snapshot.locked);
+
+        let report = snapshot.parse_report().unwrap();
+        assert_eq!(report.flagged, Some(false));
+        assert_eq!(report.lock_reason, Some("1 chargebacks".to_string()));
+    }
+
+    #[test]
+    fn lock_after_chargeback_count() {
+        let mut snapshot = build_lock_snapshot(LockPolicy::AfterChargebacks(2));
+        let mut ledger = build_charge_back_ledger(3, 1);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert!(!snapshot.locked);
+        assert!(snapshot.flagged);
+
+        let mut ledger = build_charge_back_ledger(3, 2);
+        let mut snapshot = build_lock_snapshot(LockPolicy::AfterChargebacks(2));
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert!(snapshot.locked);
+        assert_eq!(
+            snapshot.lock_reason(),
+            Some(LockReason::Chargebacks { count: 2 })
+        );
+
+        // Still flagged from the first charge back
+        let report = snapshot.parse_report().unwrap();
+        assert_eq!(report.flagged, Some(true));
+        assert_eq!(report.lock_reason, Some("2 chargebacks".to_string()));
+    }
+
+    #[test]
+    fn lock_after_chargeback_ratio() {
+        let lock = LockPolicy::ChargebackRatio { percent: 50 };
+
+        let mut snapshot = build_lock_snapshot(lock);
+        let mut ledger = build_charge_back_ledger(2, 1);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+        assert!(!snapshot.locked);
+
+        let mut snapshot = build_lock_snapshot(lock);
+        let mut ledger = build_charge_back_ledger(3, 2);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert!(snapshot.locked);
+        assert_eq!(
+            snapshot.lock_reason(),
+            Some(LockReason::ChargebackRatio {
+                chargebacks: 2,
+                deposits: 3,
+                percent: 50,
+            })
+        );
+    }
+
+    #[test]
+    fn never_lock_only_flags() {
+        let mut snapshot = build_lock_snapshot(LockPolicy::Never);
+        let mut ledger = build_charge_back_ledger(2, 2);
+
+        let res = snapshot.apply_transactions(&mut ledger);
+        assert!(res.is_ok());
+
+        assert!(!snapshot.locked);
+        assert!(snapshot.flagged);
+        assert_eq!(snapshot.lock_reason(), None);
+
+        let report = snapshot.parse_report().unwrap();
+        assert_eq!(report.flagged, Some(true));
+        assert_eq!(report.lock_reason, Some(String::new()));
+    }
+
+    fn build_opening_report(available: &str, held: &str, total: &str) -> AccountReport {
+        AccountReport {
+            client: SOME_CLIENT_ID.to_string(),
+            available: available.to_string(),
+            held: held.to_string(),
+            total: total.to_string(),
+            locked: false,
+            owed: None,
+            flagged: None,
+            lock_reason: None,
+        }
+    }
+
+    #[test]
+    fn open_from_report() {
+        let report = AccountReport {
+            locked: true,
+            ..build_opening_report("-1.5000", "3.0000", "1.5000")
+        };
+
+        let snapshot = AccountSnapshot::from_report(&report, AccountPolicy::default()).unwrap();
+
+        assert_eq!(snapshot.available, Money(-15000));
+        assert_eq!(snapshot.held, Money(30000));
+        assert!(snapshot.locked);
+        assert_eq!(snapshot.lock_reason(), Some(LockReason::OpeningBalance));
+        assert_eq!(snapshot.parse_report().unwrap(), report);
+    }
+
+    #[test]
+    fn fail_to_open_with_wrong_total() {
+        let report = build_opening_report("1.0000", "0.5000", "1.0000");
+
+        let err = AccountSnapshot::from_report(&report, AccountPolicy::default()).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<OpeningBalanceError>(),
+            Some(OpeningBalanceError::TotalMismatch {
+                expected: Money(15000),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn fail_to_open_with_untracked_debt() {
+        let report = AccountReport {
+            owed: Some("0.5000".to_string()),
+            ..build_opening_report("0.0000", "1.0000", "0.5000")
+        };
+
+        assert!(AccountSnapshot::from_report(&report, AccountPolicy::default()).is_err());
+
+        let policy = AccountPolicy {
+            debt: DebtPolicy::Track,
+            ..AccountPolicy::default()
+        };
+
+        let snapshot = AccountSnapshot::from_report(&report, policy).unwrap();
+        assert_eq!(snapshot.parse_report().unwrap(), report);
+    }
+
+    #[test]
+    fn opening_held_funds_stay_held() {
+        let report = build_opening_report("0.0000", "1.0000", "1.0000");
+        let mut snapshot = AccountSnapshot::from_report(&report, AccountPolicy::default()).unwrap();
+
+        let mut ledger = Ledger::new();
+        ledger.append(build_transaction(
+            SOME_TRANSACTION_ID,
+            SOME_CLIENT_ID,
+            TransactionType::Resolve,
+        ));
+
+        assert!(snapshot.apply_transactions(&mut ledger).is_err());
+        assert_eq!(snapshot.held, Money(10000));
+    }
 }
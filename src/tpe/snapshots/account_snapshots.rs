@@ -1,15 +1,60 @@
-use super::AccountSnapshot;
+use super::{AccountPolicy, AccountSnapshot, OpenDispute, OpeningBalanceError};
 
 use crate::ids::ClientId;
-use crate::AccountReport;
 use crate::Result;
+use crate::{AccountReport, Ledger};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Context added to the error of a group that was rolled back
+#[derive(Error, Debug)]
+#[error("Group rolled back after a failed transaction")]
+pub struct GroupRollback {
+    /// Ledger index of the entry that failed, if it could be found
+    pub failed_idx: Option<usize>,
+}
+
+/// What account reports are sorted by.
+/// Ties are broken by client ID, so the order is the same on every run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportOrder {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+
+    /// Unlocked accounts first
+    Locked,
+}
+
+impl ReportOrder {
+    /// Widened, so totals can be compared without overflowing
+    pub(crate) fn sort_key(self, snapshot: &AccountSnapshot) -> i128 {
+        match self {
+            ReportOrder::Client => 0,
+            ReportOrder::Available => i128::from(snapshot.available().0),
+            ReportOrder::Held => i128::from(snapshot.held().0),
+            ReportOrder::Total => {
+                i128::from(snapshot.available().0) + i128::from(snapshot.held().0)
+                    - i128::from(snapshot.owed().0)
+            }
+            ReportOrder::Locked => i128::from(snapshot.is_locked()),
+        }
+    }
+}
 
 /// Convenience structure for mapping client IDs to Account snapshots
-#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct AccountSnapshots {
     map: HashMap<ClientId, AccountSnapshot>,
+    policy: AccountPolicy,
+
+    /// Accounts as they were before any of the ledger was applied
+    openings: HashMap<ClientId, AccountSnapshot>,
 }
 
 impl AccountSnapshots {
@@ -17,19 +62,434 @@ impl AccountSnapshots {
         Self::default()
     }
 
+    /// Creates an empty set of snapshots, where every new account follows the given policy
+    pub fn with_policy(policy: AccountPolicy) -> Self {
+        Self {
+            map: HashMap::new(),
+            policy,
+            openings: HashMap::new(),
+        }
+    }
+
+    /// Starts an account from its report in an earlier run, before any of the ledger is applied.
+    /// See [`AccountSnapshot::from_report`] for how its balances are treated.
+    pub fn open_account(&mut self, report: &AccountReport) -> Result {
+        let snapshot = AccountSnapshot::from_report(report, self.policy)?;
+        let client_id = snapshot.client_id();
+
+        if self.openings.contains_key(&client_id) {
+            Err(OpeningBalanceError::DuplicateClient(client_id))?;
+        }
+
+        self.openings.insert(client_id, snapshot.clone());
+        self.map.insert(client_id, snapshot);
+
+        Ok(())
+    }
+
+    /// Returns a client's account as it was before any of the ledger was applied
+    pub fn opening(&self, client_id: ClientId) -> AccountSnapshot {
+        match self.openings.get(&client_id) {
+            Some(snapshot) => snapshot.clone(),
+            None => AccountSnapshot::with_policy(client_id, self.policy),
+        }
+    }
+
+    /// The policy every new account follows
+    pub fn policy(&self) -> AccountPolicy {
+        self.policy
+    }
+
+    /// Iterates over every account, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &AccountSnapshot> {
+        self.map.values()
+    }
+
+    pub fn find(&self, client_id: ClientId) -> Option<&AccountSnapshot> {
+        self.map.get(&client_id)
+    }
+
     pub fn find_mut_or_create(&mut self, client_id: ClientId) -> &mut AccountSnapshot {
+        let policy = self.policy;
+
         self.map
             .entry(client_id)
-            .or_insert_with(|| AccountSnapshot::new(client_id));
+            .or_insert_with(|| AccountSnapshot::with_policy(client_id, policy))
+    }
 
-        return self.map.get_mut(&client_id).unwrap();
+    /// Applies a group of ledger entries as one unit.
+    /// If any of them fail, every affected snapshot is rolled back and every entry is invalidated.
+    pub fn apply_group(&mut self, ledger: &mut Ledger, ledger_indicies: &[usize]) -> Result {
+        let mut client_ids: Vec<ClientId> = ledger_indicies
+            .iter()
+            .filter_map(|idx| ledger.get_by_index(idx))
+            .map(|tx| tx.client_id)
+            .collect();
+
+        client_ids.sort();
+        client_ids.dedup();
+
+        let saved: Vec<(ClientId, Option<AccountSnapshot>)> = client_ids
+            .iter()
+            .map(|client_id| (*client_id, self.map.get(client_id).cloned()))
+            .collect();
+
+        for client_id in client_ids {
+            let res = self
+                .find_mut_or_create(client_id)
+                .apply_transactions(ledger);
+
+            if let Err(e) = res {
+                let failed_idx = ledger_indicies
+                    .iter()
+                    .copied()
+                    .find(|idx| ledger.get_by_index(idx).is_some_and(|tx| tx.invalid));
+
+                for (client_id, snapshot) in saved {
+                    match snapshot {
+                        Some(snapshot) => self.map.insert(client_id, snapshot),
+                        None => self.map.remove(&client_id),
+                    };
+                }
+
+                for idx in ledger_indicies {
+                    ledger.invalidate(idx);
+                }
+
+                return Err(e.context(GroupRollback { failed_idx }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes any disputes whose window has ended by the end of the ledger
+    pub fn expire_disputes(&mut self, ledger: &Ledger) -> Result {
+        for snapshot in self.map.values_mut() {
+            expire_at_end(snapshot, ledger)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the accounts with any disputes whose window has ended by the end of the ledger closed,
+    /// so they can be reported on without changing these accounts
+    pub fn expired(&self, ledger: &Ledger) -> Result<AccountSnapshots> {
+        let mut expired = self.clone();
+        expired.expire_disputes(ledger)?;
+
+        Ok(expired)
     }
 
+    /// Copies a single account, with any disputes whose window has ended by the end of the ledger
+    /// closed. Empty if the client has no account.
+    pub fn find_expired(
+        &self,
+        client_id: ClientId,
+        ledger: &Ledger,
+    ) -> Result<Option<AccountSnapshot>> {
+        let Some(snapshot) = self.find(client_id) else {
+            return Ok(None);
+        };
+
+        let mut snapshot = snapshot.clone();
+        expire_at_end(&mut snapshot, ledger)?;
+
+        Ok(Some(snapshot))
+    }
+
+    /// Lists the open disputes of every client that has at least one, ordered by client ID
+    pub fn open_disputes(&self) -> BTreeMap<ClientId, Vec<OpenDispute>> {
+        self.map
+            .iter()
+            .map(|(&client_id, snapshot)| (client_id, snapshot.open_disputes()))
+            .filter(|(_, disputes)| !disputes.is_empty())
+            .collect()
+    }
+
+    /// Builds a report for every account, ordered by client ID
     pub fn build_report(&self) -> Result<Vec<AccountReport>> {
-        return self
-            .map
-            .values()
+        self.reports(ReportOrder::Client).collect()
+    }
+
+    /// Reports on every account in the given order.
+    /// Only the snapshots are sorted up front, and each report is built as it's needed.
+    pub fn reports(&self, order: ReportOrder) -> impl Iterator<Item = Result<AccountReport>> + '_ {
+        let mut snapshots: Vec<&AccountSnapshot> = self.map.values().collect();
+        snapshots.sort_by_key(|snapshot| (order.sort_key(snapshot), snapshot.client_id()));
+
+        snapshots
+            .into_iter()
             .map(|snapshot| snapshot.parse_report())
-            .collect::<Result<Vec<AccountReport>>>();
+    }
+}
+
+/// Closes the account's disputes whose window has ended by the end of the ledger
+fn expire_at_end(snapshot: &mut AccountSnapshot, ledger: &Ledger) -> Result {
+    if ledger.is_empty() {
+        return Ok(());
+    }
+
+    snapshot.expire_disputes(ledger.len() - 1, ledger.latest_timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ids::TransactionId, DisputeWindow, Money, Transaction, TransactionType};
+
+    use super::*;
+
+    const SOME_CLIENT_ID: ClientId = ClientId(40);
+    const OTHER_CLIENT_ID: ClientId = ClientId(41);
+
+    const SOME_AMOUNT: Money = Money(555444);
+    const OTHER_AMOUNT: Money = Money(1000);
+
+    fn build_transaction(id: u32, client_id: ClientId, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id,
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    fn build_report(snapshots: &AccountSnapshots) -> Vec<AccountReport> {
+        let mut report = snapshots.build_report().unwrap();
+        report.sort();
+        report
+    }
+
+    #[test]
+    fn apply_group() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let indicies = [
+            ledger.append(build_transaction(
+                1,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            )),
+            ledger.append(build_transaction(
+                2,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: OTHER_AMOUNT,
+                },
+            )),
+        ];
+
+        snapshots.apply_group(&mut ledger, &indicies).unwrap();
+
+        let mut expected = AccountSnapshot::new(SOME_CLIENT_ID);
+        expected.apply_transactions(&mut ledger).unwrap();
+
+        assert_eq!(
+            build_report(&snapshots),
+            vec![expected.parse_report().unwrap()]
+        );
+        assert!(!ledger.get_by_index(&0).unwrap().invalid);
+        assert!(!ledger.get_by_index(&1).unwrap().invalid);
+    }
+
+    #[test]
+    fn roll_back_failed_group() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let deposit_idx = ledger.append(build_transaction(
+            1,
+            SOME_CLIENT_ID,
+            TransactionType::Deposit {
+                amount: OTHER_AMOUNT,
+            },
+        ));
+
+        snapshots.apply_group(&mut ledger, &[deposit_idx]).unwrap();
+
+        let before = build_report(&snapshots);
+
+        let indicies = [
+            ledger.append(build_transaction(
+                2,
+                OTHER_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            )),
+            ledger.append(build_transaction(
+                3,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            )),
+            ledger.append(build_transaction(
+                4,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: Money(SOME_AMOUNT.0 + OTHER_AMOUNT.0 + 1),
+                },
+            )),
+        ];
+
+        let err = snapshots.apply_group(&mut ledger, &indicies).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<GroupRollback>().unwrap().failed_idx,
+            Some(indicies[2])
+        );
+
+        assert_eq!(build_report(&snapshots), before);
+
+        for idx in indicies.iter() {
+            assert!(ledger.get_by_index(idx).unwrap().invalid);
+        }
+
+        assert!(!ledger.get_by_index(&deposit_idx).unwrap().invalid);
+    }
+
+    #[test]
+    fn open_accounts() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let report = |client: &str, available: &str, held: &str, total: &str| AccountReport {
+            client: client.to_string(),
+            available: available.to_string(),
+            held: held.to_string(),
+            total: total.to_string(),
+            locked: false,
+            owed: None,
+            flagged: None,
+            lock_reason: None,
+        };
+
+        snapshots
+            .open_account(&report("40", "1.5", "0.5", "2.0"))
+            .unwrap();
+
+        let err = snapshots
+            .open_account(&report("40", "1.0", "0", "1.0"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OpeningBalanceError>(),
+            Some(OpeningBalanceError::DuplicateClient(SOME_CLIENT_ID))
+        ));
+
+        ledger.append(build_transaction(
+            1,
+            SOME_CLIENT_ID,
+            TransactionType::Withdrawal {
+                amount: Money(15000),
+            },
+        ));
+
+        snapshots
+            .find_mut_or_create(SOME_CLIENT_ID)
+            .apply_transactions(&mut ledger)
+            .unwrap();
+
+        assert_eq!(
+            build_report(&snapshots),
+            vec![report("40", "0.0000", "0.5000", "0.5000")]
+        );
+
+        // The opening balance is kept, so the ledger can be replayed from it
+        assert_eq!(snapshots.opening(SOME_CLIENT_ID).available(), Money(15000));
+        assert_eq!(
+            snapshots.opening(OTHER_CLIENT_ID),
+            AccountSnapshot::new(OTHER_CLIENT_ID)
+        );
+    }
+
+    #[test]
+    fn report_in_order() {
+        const FIRST_CLIENT_ID: ClientId = ClientId(39);
+
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let deposits = [
+            (OTHER_CLIENT_ID, SOME_AMOUNT),
+            (SOME_CLIENT_ID, OTHER_AMOUNT),
+            (FIRST_CLIENT_ID, SOME_AMOUNT),
+        ];
+
+        for (id, (client_id, amount)) in deposits.into_iter().enumerate() {
+            ledger.append(build_transaction(
+                id as u32,
+                client_id,
+                TransactionType::Deposit { amount },
+            ));
+
+            snapshots
+                .find_mut_or_create(client_id)
+                .apply_transactions(&mut ledger)
+                .unwrap();
+        }
+
+        let clients = |order| -> Vec<String> {
+            snapshots
+                .reports(order)
+                .map(|report| report.unwrap().client)
+                .collect()
+        };
+
+        assert_eq!(clients(ReportOrder::Client), vec!["39", "40", "41"]);
+
+        // Ties are broken by client ID
+        assert_eq!(clients(ReportOrder::Available), vec!["40", "39", "41"]);
+        assert_eq!(clients(ReportOrder::Total), vec!["40", "39", "41"]);
+    }
+
+    #[test]
+    fn expire_a_copy() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::with_policy(AccountPolicy {
+            dispute_window: DisputeWindow {
+                max_ledger_distance: Some(1),
+                ..DisputeWindow::default()
+            },
+            ..AccountPolicy::default()
+        });
+
+        let transactions = [
+            build_transaction(
+                1,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: SOME_AMOUNT,
+                },
+            ),
+            build_transaction(1, SOME_CLIENT_ID, TransactionType::Dispute),
+            build_transaction(
+                2,
+                OTHER_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: OTHER_AMOUNT,
+                },
+            ),
+        ];
+
+        for tx in transactions {
+            let client_id = tx.client_id;
+            ledger.append(tx);
+
+            snapshots
+                .find_mut_or_create(client_id)
+                .apply_transactions(&mut ledger)
+                .unwrap();
+        }
+
+        let expired = snapshots.expired(&ledger).unwrap();
+
+        // Only the copy has the dispute closed
+        assert!(expired.open_disputes().is_empty());
+        assert_eq!(snapshots.open_disputes()[&SOME_CLIENT_ID].len(), 1);
     }
 }
@@ -0,0 +1,148 @@
+use crate::ids::TransactionId;
+use crate::Money;
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The dispute lifecycle of a single deposit
+///
+/// ```text
+/// Undisputed --dispute--> Disputed --resolve-----> Resolved --dispute--> Disputed ...
+///                                  --charge back-> ChargedBack
+/// ```
+///
+/// A resolved deposit can only be disputed again while the policy allows more re-disputes.
+/// A charged back deposit is final.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+/// An action that moves a deposit between dispute states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeEvent {
+    Dispute,
+    Resolve,
+    ChargeBack,
+}
+
+impl DisputeState {
+    /// Returns the state reached by applying the event, or None if the transition isn't allowed.
+    ///
+    /// `dispute_count` is how many times the deposit has already been disputed, and
+    /// `max_redisputes` is how many times it may be disputed again after being resolved.
+    pub fn transition(
+        self,
+        event: DisputeEvent,
+        dispute_count: u32,
+        max_redisputes: Option<u32>,
+    ) -> Option<DisputeState> {
+        match (self, event) {
+            (DisputeState::Undisputed, DisputeEvent::Dispute) => Some(DisputeState::Disputed),
+
+            (DisputeState::Resolved, DisputeEvent::Dispute) => {
+                let redisputes = dispute_count.saturating_sub(1);
+
+                match max_redisputes {
+                    Some(max) if redisputes >= max => None,
+                    _ => Some(DisputeState::Disputed),
+                }
+            }
+
+            (DisputeState::Disputed, DisputeEvent::Resolve) => Some(DisputeState::Resolved),
+            (DisputeState::Disputed, DisputeEvent::ChargeBack) => Some(DisputeState::ChargedBack),
+
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DisputeState::Undisputed => "undisputed",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged back",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// A disputed deposit that is currently holding funds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDispute {
+    pub tx_id: TransactionId,
+    pub amount: Money,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispute_lifecycle() {
+        let state = DisputeState::Undisputed;
+
+        let state = state.transition(DisputeEvent::Dispute, 0, None).unwrap();
+        assert_eq!(state, DisputeState::Disputed);
+
+        let state = state.transition(DisputeEvent::Resolve, 1, None).unwrap();
+        assert_eq!(state, DisputeState::Resolved);
+
+        let state = state.transition(DisputeEvent::Dispute, 1, None).unwrap();
+        assert_eq!(state, DisputeState::Disputed);
+
+        let state = state.transition(DisputeEvent::ChargeBack, 2, None).unwrap();
+        assert_eq!(state, DisputeState::ChargedBack);
+    }
+
+    #[test]
+    fn fail_to_close_undisputed() {
+        let state = DisputeState::Undisputed;
+
+        assert!(state.transition(DisputeEvent::Resolve, 0, None).is_none());
+        assert!(state
+            .transition(DisputeEvent::ChargeBack, 0, None)
+            .is_none());
+    }
+
+    #[test]
+    fn fail_to_dispute_twice() {
+        let state = DisputeState::Disputed;
+
+        assert!(state.transition(DisputeEvent::Dispute, 1, None).is_none());
+    }
+
+    #[test]
+    fn fail_to_change_charged_back() {
+        let state = DisputeState::ChargedBack;
+
+        assert!(state.transition(DisputeEvent::Dispute, 1, None).is_none());
+        assert!(state.transition(DisputeEvent::Resolve, 1, None).is_none());
+        assert!(state
+            .transition(DisputeEvent::ChargeBack, 1, None)
+            .is_none());
+    }
+
+    #[test]
+    fn limit_redisputes() {
+        let state = DisputeState::Resolved;
+
+        assert!(state
+            .transition(DisputeEvent::Dispute, 1, Some(0))
+            .is_none());
+        assert!(state
+            .transition(DisputeEvent::Dispute, 1, Some(1))
+            .is_some());
+        assert!(state
+            .transition(DisputeEvent::Dispute, 2, Some(1))
+            .is_none());
+    }
+}
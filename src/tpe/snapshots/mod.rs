@@ -1,5 +1,11 @@
+mod account_policy;
 mod account_snapshot;
 mod account_snapshots;
+mod dispute;
 
-pub use account_snapshot::AccountSnapshot;
-pub use account_snapshots::AccountSnapshots;
+pub use account_policy::{
+    AccountPolicy, DebtPolicy, DisputeExpiry, DisputeWindow, LockPolicy, LockReason,
+};
+pub use account_snapshot::{AccountSnapshot, AccountTransactionError, OpeningBalanceError};
+pub use account_snapshots::{AccountSnapshots, GroupRollback, ReportOrder};
+pub use dispute::{DisputeEvent, DisputeState, OpenDispute};
@@ -0,0 +1,172 @@
+use crate::Result;
+
+use std::{
+    fs::File,
+    io::{self, Read, Stdin},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+/// A stream of raw transaction data, such as a CSV file, stdin, or another thread
+pub trait TransactionSource: Read + Send {
+    /// Describes where the data is coming from, for logging
+    fn describe(&self) -> String;
+}
+
+/// Reads transaction data piped into the process
+pub struct StdinSource {
+    stdin: Stdin,
+}
+
+impl StdinSource {
+    pub fn new() -> Self {
+        Self { stdin: io::stdin() }
+    }
+}
+
+impl Default for StdinSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for StdinSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl TransactionSource for StdinSource {
+    fn describe(&self) -> String {
+        "stdin".to_string()
+    }
+}
+
+/// Reads transaction data from a file
+pub struct FileSource {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+}
+
+impl Read for FileSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl TransactionSource for FileSource {
+    fn describe(&self) -> String {
+        format!("{:?}", self.path)
+    }
+}
+
+/// Reads transaction data sent from another thread.
+/// The source ends once every sender has been dropped.
+pub struct ChannelSource {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelSource {
+    /// Creates a connected sender and source
+    pub fn channel() -> (Sender<Vec<u8>>, Self) {
+        let (sender, receiver) = mpsc::channel();
+
+        (sender, Self::from(receiver))
+    }
+}
+
+impl From<Receiver<Vec<u8>>> for ChannelSource {
+    fn from(receiver: Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+
+                // All senders are gone, so there's nothing left to read
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let remaining = &self.chunk[self.pos..];
+        let len = remaining.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+impl TransactionSource for ChannelSource {
+    fn describe(&self) -> String {
+        "channel".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn read_channel_until_senders_dropped() {
+        let (sender, mut source) = ChannelSource::channel();
+
+        let handle = thread::spawn(move || {
+            sender.send(b"type,client,".to_vec()).unwrap();
+            sender.send(vec![]).unwrap();
+            sender.send(b"tx,amount\n".to_vec()).unwrap();
+        });
+
+        let mut output = String::new();
+        source.read_to_string(&mut output).unwrap();
+
+        handle.join().unwrap();
+
+        assert_eq!(output, "type,client,tx,amount\n");
+    }
+
+    #[test]
+    fn read_channel_into_small_buffer() {
+        let (sender, mut source) = ChannelSource::channel();
+
+        sender.send(b"abcde".to_vec()).unwrap();
+        drop(sender);
+
+        let mut buf = [0; 2];
+
+        assert_eq!(source.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"ab");
+        assert_eq!(source.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"cd");
+        assert_eq!(source.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"e");
+        assert_eq!(source.read(&mut buf).unwrap(), 0);
+    }
+}
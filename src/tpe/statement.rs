@@ -0,0 +1,321 @@
+//! Statements of a single client's activity, rebuilt from the ledger.
+
+use crate::ids::{ClientId, TransactionId};
+use crate::{AccountSnapshot, AccountSnapshots, Ledger, Money, Result, TransactionType};
+
+/// Which of the client's ledger entries a statement lists.
+/// Bounds are inclusive, and a range without bounds covers the whole ledger.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatementRange {
+    pub from_idx: Option<usize>,
+    pub to_idx: Option<usize>,
+
+    /// Seconds since the Unix epoch.
+    /// Entries without a timestamp are placed at the time of the client's entry before them.
+    pub from_time: Option<u64>,
+    pub to_time: Option<u64>,
+}
+
+impl StatementRange {
+    fn is_before(&self, ledger_idx: usize, time: u64) -> bool {
+        self.from_idx.is_some_and(|from_idx| ledger_idx < from_idx)
+            || self.from_time.is_some_and(|from_time| time < from_time)
+    }
+
+    fn is_after(&self, ledger_idx: usize, time: u64) -> bool {
+        self.to_idx.is_some_and(|to_idx| ledger_idx > to_idx)
+            || self.to_time.is_some_and(|to_time| time > to_time)
+    }
+
+    fn is_open_ended(&self) -> bool {
+        self.to_idx.is_none() && self.to_time.is_none()
+    }
+}
+
+/// An account's balances at a point in its statement
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Money,
+    pub held: Money,
+
+    /// Available and held, less anything owed
+    pub total: Money,
+}
+
+impl Balances {
+    fn of(snapshot: &AccountSnapshot) -> Result<Self> {
+        let mut total = snapshot.available();
+        total.add(&snapshot.held())?;
+        total.sub(&snapshot.owed())?;
+
+        Ok(Self {
+            available: snapshot.available(),
+            held: snapshot.held(),
+            total,
+        })
+    }
+}
+
+/// Whether a ledger entry was applied to the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    Accepted,
+
+    /// Invalidated in the ledger, so it didn't change the balances
+    Rejected,
+}
+
+/// A single ledger entry in a statement, with the balances after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementEntry {
+    pub ledger_idx: usize,
+    pub tx_id: TransactionId,
+    pub tx_type: TransactionType,
+    pub timestamp: Option<u64>,
+    pub status: EntryStatus,
+    pub balances: Balances,
+}
+
+/// A client's ledger entries over a range, with running balances
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub client_id: ClientId,
+
+    /// Balances before the first entry in the range
+    pub opening: Balances,
+
+    pub entries: Vec<StatementEntry>,
+
+    /// Balances after the last entry in the range
+    pub closing: Balances,
+}
+
+impl Statement {
+    /// Builds a statement by replaying the client's ledger entries on the account as it was
+    /// before the ledger, ie. from its opening balance, following the same policy.
+    ///
+    /// When the range has no end, disputes that expired at the end of the ledger are closed too,
+    /// so the closing balances match the account's report.
+    pub fn build(
+        ledger: &Ledger,
+        snapshots: &AccountSnapshots,
+        client_id: ClientId,
+        range: StatementRange,
+    ) -> Result<Self> {
+        let mut snapshot = snapshots.opening(client_id);
+
+        let mut opening = None;
+        let mut entries = vec![];
+        let mut time = 0;
+
+        for ledger_idx in 0..ledger.len() {
+            let tx = match ledger.get_by_index(&ledger_idx) {
+                Some(tx) if tx.client_id == client_id => tx,
+                _ => continue,
+            };
+
+            time = tx.timestamp.unwrap_or(time);
+
+            if range.is_after(ledger_idx, time) {
+                break;
+            }
+
+            let in_range = !range.is_before(ledger_idx, time);
+
+            if in_range && opening.is_none() {
+                opening = Some(Balances::of(&snapshot)?);
+            }
+
+            let status = if tx.invalid {
+                EntryStatus::Rejected
+            } else {
+                snapshot.replay(ledger, ledger_idx)?;
+                EntryStatus::Accepted
+            };
+
+            if in_range {
+                entries.push(StatementEntry {
+                    ledger_idx,
+                    tx_id: tx.id,
+                    tx_type: tx.tx_type.clone(),
+                    timestamp: tx.timestamp,
+                    status,
+                    balances: Balances::of(&snapshot)?,
+                });
+            }
+        }
+
+        let opening = match opening {
+            Some(opening) => opening,
+            None => Balances::of(&snapshot)?,
+        };
+
+        if range.is_open_ended() && !ledger.is_empty() {
+            snapshot.expire_disputes(ledger.len() - 1, ledger.latest_timestamp())?;
+        }
+
+        Ok(Self {
+            client_id,
+            opening,
+            entries,
+            closing: Balances::of(&snapshot)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Transaction;
+
+    const SOME_CLIENT_ID: ClientId = ClientId(40);
+    const OTHER_CLIENT_ID: ClientId = ClientId(41);
+
+    fn build_transaction(
+        id: u32,
+        client_id: ClientId,
+        tx_type: TransactionType,
+        timestamp: Option<u64>,
+    ) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id,
+            tx_type,
+            timestamp,
+            invalid: false,
+        }
+    }
+
+    /// Deposits 10, fails to withdraw 20, withdraws 3, then disputes the deposit.
+    /// Another client's deposit is in between.
+    fn build_ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+
+        let transactions = [
+            (
+                1,
+                SOME_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: Money(100000),
+                },
+                Some(10),
+            ),
+            (
+                2,
+                OTHER_CLIENT_ID,
+                TransactionType::Deposit {
+                    amount: Money(50000),
+                },
+                Some(15),
+            ),
+            (
+                3,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: Money(200000),
+                },
+                Some(20),
+            ),
+            (
+                4,
+                SOME_CLIENT_ID,
+                TransactionType::Withdrawal {
+                    amount: Money(30000),
+                },
+                None,
+            ),
+            (1, SOME_CLIENT_ID, TransactionType::Dispute, Some(30)),
+        ];
+
+        for (id, client_id, tx_type, timestamp) in transactions {
+            ledger.append(build_transaction(id, client_id, tx_type, timestamp));
+        }
+
+        let mut snapshot = AccountSnapshot::new(SOME_CLIENT_ID);
+
+        while snapshot.apply_transactions(&mut ledger).is_err() {}
+
+        ledger
+    }
+
+    fn balances(available: i64, held: i64) -> Balances {
+        Balances {
+            available: Money(available),
+            held: Money(held),
+            total: Money(available + held),
+        }
+    }
+
+    #[test]
+    fn build_full_statement() {
+        let ledger = build_ledger();
+
+        let statement = Statement::build(
+            &ledger,
+            &AccountSnapshots::new(),
+            SOME_CLIENT_ID,
+            StatementRange::default(),
+        )
+        .unwrap();
+
+        let summary: Vec<(usize, EntryStatus, Balances)> = statement
+            .entries
+            .iter()
+            .map(|entry| (entry.ledger_idx, entry.status, entry.balances))
+            .collect();
+
+        assert_eq!(statement.opening, Balances::default());
+        assert_eq!(
+            summary,
+            vec![
+                (0, EntryStatus::Accepted, balances(100000, 0)),
+                (2, EntryStatus::Rejected, balances(100000, 0)),
+                (3, EntryStatus::Accepted, balances(70000, 0)),
+                (4, EntryStatus::Accepted, balances(-30000, 100000)),
+            ]
+        );
+        assert_eq!(statement.closing, balances(-30000, 100000));
+    }
+
+    #[test]
+    fn build_statement_for_index_range() {
+        let ledger = build_ledger();
+
+        let range = StatementRange {
+            from_idx: Some(1),
+            to_idx: Some(3),
+            ..StatementRange::default()
+        };
+
+        let statement =
+            Statement::build(&ledger, &AccountSnapshots::new(), SOME_CLIENT_ID, range).unwrap();
+
+        let indicies: Vec<usize> = statement.entries.iter().map(|e| e.ledger_idx).collect();
+
+        assert_eq!(indicies, vec![2, 3]);
+        assert_eq!(statement.opening, balances(100000, 0));
+        assert_eq!(statement.closing, balances(70000, 0));
+    }
+
+    #[test]
+    fn build_statement_for_time_range() {
+        let ledger = build_ledger();
+
+        // The withdrawal without a timestamp is placed at the time of the one before it
+        let range = StatementRange {
+            from_time: Some(20),
+            to_time: Some(25),
+            ..StatementRange::default()
+        };
+
+        let statement =
+            Statement::build(&ledger, &AccountSnapshots::new(), SOME_CLIENT_ID, range).unwrap();
+
+        let indicies: Vec<usize> = statement.entries.iter().map(|e| e.ledger_idx).collect();
+
+        assert_eq!(indicies, vec![2, 3]);
+        assert_eq!(statement.opening, balances(100000, 0));
+        assert_eq!(statement.closing, balances(70000, 0));
+    }
+}
@@ -0,0 +1,285 @@
+//! Statistics about a processed ledger, and the accounts it was applied to.
+
+use crate::snapshots::ReportOrder;
+use crate::{AccountReport, AccountSnapshots, Ledger, Money, Result, TransactionType};
+
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use serde::Serialize;
+
+/// Counts for a single transaction type
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TypeSummary {
+    pub accepted: usize,
+
+    /// Invalidated in the ledger
+    pub rejected: usize,
+
+    /// Sum of the accepted amounts, for types that have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+}
+
+/// How many disputes were opened, and how they were closed.
+/// Only disputes in the ledger are counted, not those closed when their window expired.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeSummary {
+    pub opened: usize,
+    pub resolved: usize,
+    pub charged_back: usize,
+}
+
+/// Statistics about a ledger, and the accounts it was applied to
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// Keyed by the type name, as it's written in input rows
+    pub transactions: BTreeMap<&'static str, TypeSummary>,
+
+    pub disputes: DisputeSummary,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+
+    /// Funds held across every account
+    pub total_held: String,
+
+    /// Accounts with the largest totals, largest first
+    pub largest_balances: Vec<AccountReport>,
+}
+
+impl Summary {
+    /// Builds a summary, listing up to the given number of accounts with the largest balances.
+    /// Only ledger entries from `from_idx` on are counted, ie. those appended since resuming,
+    /// while the accounts are summarized as they stand.
+    pub fn build(
+        ledger: &Ledger,
+        snapshots: &AccountSnapshots,
+        from_idx: usize,
+        largest: usize,
+    ) -> Result<Self> {
+        let mut transactions: BTreeMap<&'static str, TypeSummary> = BTreeMap::new();
+        let mut amounts: BTreeMap<&'static str, Money> = BTreeMap::new();
+        let mut disputes = DisputeSummary::default();
+
+        for ledger_idx in from_idx..ledger.len() {
+            let Some(tx) = ledger.get_by_index(&ledger_idx) else {
+                continue;
+            };
+
+            let name = tx.tx_type.name();
+            let summary = transactions.entry(name).or_default();
+
+            if tx.invalid {
+                summary.rejected += 1;
+                continue;
+            }
+
+            summary.accepted += 1;
+
+            if let Some(amount) = tx.tx_type.amount() {
+                amounts.entry(name).or_default().add(&amount)?;
+            }
+
+            match tx.tx_type {
+                TransactionType::Dispute => disputes.opened += 1,
+                TransactionType::Resolve => disputes.resolved += 1,
+                TransactionType::ChargeBack => disputes.charged_back += 1,
+                _ => {}
+            }
+        }
+
+        for (name, amount) in amounts {
+            if let Some(summary) = transactions.get_mut(name) {
+                summary.amount = Some(amount.to_string());
+            }
+        }
+
+        let mut total_held = Money(0);
+        let mut locked_accounts = 0;
+
+        for snapshot in snapshots.iter() {
+            total_held.add(&snapshot.held())?;
+
+            if snapshot.is_locked() {
+                locked_accounts += 1;
+            }
+        }
+
+        let mut by_total: Vec<_> = snapshots.iter().collect();
+        by_total.sort_by_key(|snapshot| {
+            (
+                Reverse(ReportOrder::Total.sort_key(snapshot)),
+                snapshot.client_id(),
+            )
+        });
+
+        let largest_balances = by_total
+            .into_iter()
+            .take(largest)
+            .map(|snapshot| snapshot.parse_report())
+            .collect::<Result<Vec<AccountReport>>>()?;
+
+        Ok(Self {
+            transactions,
+            disputes,
+            accounts: snapshots.iter().count(),
+            locked_accounts,
+            total_held: total_held.to_string(),
+            largest_balances,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{ClientId, TransactionId};
+    use crate::Transaction;
+
+    fn build_transaction(id: u32, client_id: u16, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            id: TransactionId(id),
+            client_id: ClientId(client_id),
+            tx_type,
+            timestamp: None,
+            invalid: false,
+        }
+    }
+
+    #[test]
+    fn build_summary() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let transactions = [
+            build_transaction(
+                1,
+                1,
+                TransactionType::Deposit {
+                    amount: Money(50000),
+                },
+            ),
+            build_transaction(
+                2,
+                2,
+                TransactionType::Deposit {
+                    amount: Money(20000),
+                },
+            ),
+            build_transaction(
+                3,
+                3,
+                TransactionType::Deposit {
+                    amount: Money(20000),
+                },
+            ),
+            build_transaction(
+                4,
+                1,
+                TransactionType::Withdrawal {
+                    amount: Money(90000),
+                },
+            ),
+            build_transaction(1, 1, TransactionType::Dispute),
+            build_transaction(2, 2, TransactionType::Dispute),
+            build_transaction(2, 2, TransactionType::ChargeBack),
+        ];
+
+        for tx in transactions {
+            let client_id = tx.client_id;
+            ledger.append(tx);
+
+            let _ = snapshots
+                .find_mut_or_create(client_id)
+                .apply_transactions(&mut ledger);
+        }
+
+        let summary = Summary::build(&ledger, &snapshots, 0, 2).unwrap();
+
+        assert_eq!(
+            summary.transactions["deposit"],
+            TypeSummary {
+                accepted: 3,
+                rejected: 0,
+                amount: Some("9.0000".to_string()),
+            }
+        );
+        assert_eq!(
+            summary.transactions["withdrawal"],
+            TypeSummary {
+                accepted: 0,
+                rejected: 1,
+                amount: None,
+            }
+        );
+        assert_eq!(
+            summary.disputes,
+            DisputeSummary {
+                opened: 2,
+                resolved: 0,
+                charged_back: 1,
+            }
+        );
+        assert_eq!(summary.accounts, 3);
+        assert_eq!(summary.locked_accounts, 1);
+        assert_eq!(summary.total_held, "5.0000");
+
+        // Client 1's funds are all held, so their total is still the largest
+        let largest: Vec<&str> = summary
+            .largest_balances
+            .iter()
+            .map(|report| report.client.as_str())
+            .collect();
+
+        assert_eq!(largest, vec!["1", "3"]);
+    }
+
+    #[test]
+    fn build_summary_from_index() {
+        let mut ledger = Ledger::new();
+        let mut snapshots = AccountSnapshots::new();
+
+        let transactions = [
+            build_transaction(
+                1,
+                1,
+                TransactionType::Deposit {
+                    amount: Money(50000),
+                },
+            ),
+            build_transaction(
+                2,
+                1,
+                TransactionType::Deposit {
+                    amount: Money(20000),
+                },
+            ),
+            build_transaction(1, 1, TransactionType::Dispute),
+        ];
+
+        for tx in transactions {
+            let client_id = tx.client_id;
+            ledger.append(tx);
+
+            let _ = snapshots
+                .find_mut_or_create(client_id)
+                .apply_transactions(&mut ledger);
+        }
+
+        let summary = Summary::build(&ledger, &snapshots, 1, 1).unwrap();
+
+        assert_eq!(
+            summary.transactions["deposit"],
+            TypeSummary {
+                accepted: 1,
+                rejected: 0,
+                amount: Some("2.0000".to_string()),
+            }
+        );
+        assert_eq!(summary.disputes.opened, 1);
+
+        // The accounts still hold everything applied to them
+        assert_eq!(summary.total_held, "5.0000");
+        assert_eq!(summary.largest_balances[0].total, "7.0000");
+    }
+}
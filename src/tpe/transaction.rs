@@ -1,20 +1,57 @@
 use crate::ids::{ClientId, TransactionId};
 use crate::Money;
 
+use serde::{Deserialize, Serialize};
+
 /// Transaction represents a requested change to an account
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Transaction {
     pub id: TransactionId,
     pub client_id: ClientId,
     pub tx_type: TransactionType,
+
+    /// Seconds since the Unix epoch, when provided by the input
+    pub timestamp: Option<u64>,
+
     pub invalid: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum TransactionType {
-    Deposit { amount: Money },
-    Withdrawal { amount: Money },
+    Deposit {
+        amount: Money,
+    },
+    Withdrawal {
+        amount: Money,
+    },
     Dispute,
     Resolve,
     ChargeBack,
+
+    /// Administrative write-off of any debt the client owes
+    WriteOff,
+}
+
+impl TransactionType {
+    /// The name of the type, as it's written in input rows
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit { .. } => "deposit",
+            TransactionType::Withdrawal { .. } => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::ChargeBack => "chargeback",
+            TransactionType::WriteOff => "writeoff",
+        }
+    }
+
+    /// The amount of a deposit or withdrawal. Other types refer to an earlier transaction instead
+    pub fn amount(&self) -> Option<Money> {
+        match self {
+            TransactionType::Deposit { amount } | TransactionType::Withdrawal { amount } => {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
 }
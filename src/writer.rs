@@ -1,15 +1,177 @@
-use tpe::Result;
+use tpe::checkpoint;
+use tpe::reconcile::Break;
+use tpe::report::{CsvSink, JsonLinesSink, JsonNumbers, JsonSink, MarkdownSink, ReportSink};
+use tpe::statement::{Balances, EntryStatus, Statement};
+use tpe::summary::Summary;
+use tpe::{AccountSnapshots, Ledger, Result};
+
+use crate::rejects::RejectStage;
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
 
 use csv::Writer;
+use serde::Serialize;
+
+/// Supported formats for the account report
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+
+    /// A single JSON array
+    Json,
+    JsonLines,
+
+    /// A Markdown table
+    Markdown,
+}
+
+/// Builds a report sink in the given format, writing to the given file or stdout if there isn't one.
+/// Output is buffered, rather than held in memory until it's all written.
+pub fn build_report_sink(
+    path: Option<&Path>,
+    format: OutputFormat,
+    json_numbers: JsonNumbers,
+) -> Result<Box<dyn ReportSink>> {
+    let output = open_output(path)?;
+
+    let sink: Box<dyn ReportSink> = match format {
+        OutputFormat::Csv => Box::new(CsvSink::new(output)),
+        OutputFormat::Json => Box::new(JsonSink::new(output, json_numbers)),
+        OutputFormat::JsonLines => Box::new(JsonLinesSink::new(output, json_numbers)),
+        OutputFormat::Markdown => Box::new(MarkdownSink::new(output)),
+    };
+
+    Ok(sink)
+}
 
-/// Builds an empty csv writer
-pub fn build_csv_writer() -> Writer<Vec<u8>> {
-    Writer::from_writer(vec![])
+/// Opens the given file for writing, or stdout if there isn't one
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let output: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    Ok(output)
+}
+
+/// A row of a statement, either a ledger entry or the opening or closing balances
+#[derive(Serialize)]
+struct StatementRow {
+    /// `opening`, `entry` or `closing`
+    row: &'static str,
+    ledger_index: Option<usize>,
+    tx: Option<u32>,
+
+    #[serde(rename = "type")]
+    typ: Option<&'static str>,
+
+    amount: Option<String>,
+    timestamp: Option<u64>,
+    status: Option<&'static str>,
+    available: String,
+    held: String,
+    total: String,
 }
 
-/// Writes the contents of the csv::Writer to a String
-pub fn write_to_string(writer: Writer<Vec<u8>>) -> Result<String> {
-    let utf8 = writer.into_inner()?;
-    let string = String::from_utf8(utf8)?;
-    Ok(string)
+impl StatementRow {
+    fn balances(row: &'static str, balances: &Balances) -> Self {
+        Self {
+            row,
+            ledger_index: None,
+            tx: None,
+            typ: None,
+            amount: None,
+            timestamp: None,
+            status: None,
+            available: balances.available.to_string(),
+            held: balances.held.to_string(),
+            total: balances.total.to_string(),
+        }
+    }
+}
+
+/// Writes a statement as CSV to the given file, or stdout if there isn't one
+pub fn write_statement(path: Option<&Path>, statement: &Statement) -> Result {
+    let mut wtr = Writer::from_writer(open_output(path)?);
+
+    wtr.serialize(StatementRow::balances("opening", &statement.opening))?;
+
+    for entry in statement.entries.iter() {
+        let status = match entry.status {
+            EntryStatus::Accepted => "accepted",
+            EntryStatus::Rejected => "rejected",
+        };
+
+        wtr.serialize(StatementRow {
+            ledger_index: Some(entry.ledger_idx),
+            tx: Some(entry.tx_id.0),
+            typ: Some(entry.tx_type.name()),
+            amount: entry.tx_type.amount().map(|amount| amount.to_string()),
+            timestamp: entry.timestamp,
+            status: Some(status),
+            ..StatementRow::balances("entry", &entry.balances)
+        })?;
+    }
+
+    wtr.serialize(StatementRow::balances("closing", &statement.closing))?;
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Statistics about a run: what was read, and what it did to the ledger and accounts
+#[derive(Serialize)]
+pub struct RunSummary<'a> {
+    pub rows_read: u64,
+    pub duplicates_skipped: usize,
+    pub rejected: &'a BTreeMap<RejectStage, usize>,
+
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// Writes the summary as JSON to the given file
+pub fn write_summary(path: &Path, summary: &RunSummary) -> Result {
+    let mut output = BufWriter::new(File::create(path)?);
+
+    serde_json::to_writer_pretty(&mut output, summary)?;
+    writeln!(output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Writes a checkpoint of the engine to the given file.
+/// It's written alongside first, then moved into place, so an existing checkpoint is never left
+/// half written.
+pub fn write_checkpoint(path: &Path, ledger: &Ledger, snapshots: &AccountSnapshots) -> Result {
+    let partial = path.with_extension("partial");
+
+    checkpoint::write(BufWriter::new(File::create(&partial)?), ledger, snapshots)?;
+    fs::rename(&partial, path)?;
+
+    Ok(())
+}
+
+/// Writes reconciliation breaks as CSV to the given file, or stdout if there isn't one
+pub fn write_breaks(path: Option<&Path>, breaks: &[Break]) -> Result {
+    let mut wtr = Writer::from_writer(open_output(path)?);
+
+    if breaks.is_empty() {
+        wtr.write_record(["client", "break", "field", "expected", "actual"])?;
+    }
+
+    for b in breaks.iter() {
+        wtr.serialize(b)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
 }
@@ -1,6 +1,13 @@
 use tpe::AccountReport;
 
-use std::{fs, path::PathBuf, process::Command};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    str, thread,
+};
 
 use csv::{ReaderBuilder, Trim};
 
@@ -59,3 +66,1130 @@ fn example_files() {
         assert_eq!(actual, expected);
     }
 }
+
+fn parse_reports(csv: &str) -> Vec<AccountReport> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(csv.as_bytes());
+
+    reader.deserialize().map(|record| record.unwrap()).collect()
+}
+
+/// Checks that two CSV reports have the same accounts, in any order
+fn assert_same_reports(actual: &[u8], expected: &[u8]) {
+    let mut actual = parse_reports(str::from_utf8(actual).unwrap());
+    let mut expected = parse_reports(str::from_utf8(expected).unwrap());
+
+    actual.sort();
+    expected.sort();
+
+    assert_eq!(actual, expected);
+}
+
+/// Runs the engine with the given args, and checks its report against the expected file
+fn run_and_compare(args: &[&str], expected_file: &Path) -> Output {
+    let output = Command::new("cargo")
+        .args(["run", "--"])
+        .args(args)
+        .output()
+        .unwrap();
+
+    println!("{}", String::from_utf8_lossy(&output.stderr));
+
+    assert_same_reports(&output.stdout, &fs::read(expected_file).unwrap());
+
+    output
+}
+
+#[test]
+fn stdin_input() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs/transactions_1.csv");
+    let expected_file = PathBuf::from("./resources/test-examples/expected/accounts_1.csv");
+
+    let mut child = Command::new("cargo")
+        .args(["run", "--", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let input = fs::read(input_file).unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+
+    assert_same_reports(&output.stdout, &fs::read(expected_file).unwrap());
+}
+
+#[test]
+fn jsonl_input() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs-jsonl/transactions_1.jsonl");
+    let expected_file = PathBuf::from("./resources/test-examples/expected/accounts_1.csv");
+
+    run_and_compare(&[input_file.to_str().unwrap()], &expected_file);
+}
+
+#[test]
+fn csv_dialect_input() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs-dialect/transactions_1.csv");
+    let expected_file = PathBuf::from("./resources/test-examples/expected/accounts_1.csv");
+
+    run_and_compare(
+        &[
+            input_file.to_str().unwrap(),
+            "--delimiter",
+            ";",
+            "--column",
+            "type=kind",
+            "--column",
+            "client=account",
+            "--column",
+            "tx=2",
+            "--column",
+            "amount=value",
+            "--type-alias",
+            "DEP=deposit",
+            "--type-alias",
+            "WD=withdrawal",
+        ],
+        &expected_file,
+    );
+}
+
+#[test]
+fn rejects_file() {
+    let rejects_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("rejects.csv");
+
+    let mut child = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-",
+            "--rejects",
+            rejects_file.to_str().unwrap(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5\n\
+                 withdrawal,1,2,10\n\
+                 deposit,1,3,-1\n\
+                 deposit,x,4,1\n\
+                 \"withdrawal\",1,5,10.0\n";
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+
+    let mut reader = ReaderBuilder::new().from_path(rejects_file).unwrap();
+
+    let rejects: Vec<(u64, String, String, String, String)> =
+        reader.deserialize().map(|record| record.unwrap()).collect();
+
+    let rejects: Vec<_> = rejects
+        .into_iter()
+        .map(|(line, stage, reason, _message, record)| (line, stage, reason, record))
+        .collect();
+
+    assert_eq!(
+        rejects,
+        vec![
+            (
+                3,
+                "apply".to_string(),
+                "invalid_withdrawal".to_string(),
+                "withdrawal,1,2,10".to_string()
+            ),
+            (
+                4,
+                "parse".to_string(),
+                "negative_amount".to_string(),
+                "deposit,1,3,-1".to_string()
+            ),
+            (
+                5,
+                "deserialize".to_string(),
+                "malformed_record".to_string(),
+                "deposit,x,4,1".to_string()
+            ),
+            // Kept exactly as it was read, quotes and all, so it can be resubmitted as it was
+            (
+                6,
+                "apply".to_string(),
+                "invalid_withdrawal".to_string(),
+                "\"withdrawal\",1,5,10.0".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let clean_file = PathBuf::from("./resources/test-examples/inputs/transactions_7.csv");
+    let rejects_file = PathBuf::from("./resources/test-examples/inputs/transactions_2.csv");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[clean_file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&[rejects_file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!output.stdout.is_empty());
+
+    let output = run(&[rejects_file.to_str().unwrap(), "--strict"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = run(&["./resources/test-examples/missing.csv"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = run(&[clean_file.to_str().unwrap(), "--unknown-flag"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn unwritable_outputs() {
+    let input_file = "./resources/test-examples/inputs/transactions_7.csv";
+    let missing_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing-dir");
+    let output_file = missing_dir.join("accounts.csv");
+    let rejects_file = missing_dir.join("rejects.csv");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", input_file])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Named by their path, rather than mistaken for unreadable input
+    for (flag, path) in [("--output", &output_file), ("--rejects", &rejects_file)] {
+        let output = run(&[flag, path.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(7));
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(&format!("Couldn't write to {path:?}")));
+    }
+}
+
+#[test]
+fn bad_dispute_references() {
+    let input_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("bad_disputes.csv");
+
+    // Disputes naming a write-off, a withdrawal, another client's deposit, and an unknown ID
+    fs::write(
+        &input_file,
+        "type,client,tx,amount\n\
+         deposit,1,1,5.0\n\
+         withdrawal,1,2,5.0\n\
+         dispute,1,1,\n\
+         chargeback,1,1,\n\
+         writeoff,1,3,\n\
+         deposit,2,4,1.0\n\
+         dispute,1,3,\n\
+         dispute,2,2,\n\
+         dispute,2,1,\n\
+         resolve,2,99,\n",
+    )
+    .unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", input_file.to_str().unwrap()])
+            .args(["--track-debt", "--never-lock"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Rejected like any other bad row, rather than stopping as an invalid ledger state
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked,owed,flagged,lock_reason\n\
+         1,0.0000,0.0000,0.0000,false,0.0000,true,\n\
+         2,1.0000,0.0000,1.0000,false,0.0000,false,\n"
+    );
+
+    let output = run(&["--strict"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn control_totals() {
+    let control_dir = PathBuf::from("./resources/test-examples/inputs-control");
+    let expected_file = PathBuf::from("./resources/test-examples/expected/accounts_1.csv");
+
+    let matching_file = control_dir.join("transactions_1.csv");
+    let truncated_file = control_dir.join("transactions_2.csv");
+    let manifest_file = control_dir.join("manifest_1.csv");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    run_and_compare(
+        &[
+            matching_file.to_str().unwrap(),
+            "--require-trailer",
+            "--control",
+            manifest_file.to_str().unwrap(),
+        ],
+        &expected_file,
+    );
+
+    let outcomes_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("control_outcomes.csv");
+
+    let output = run(&[
+        truncated_file.to_str().unwrap(),
+        "--outcomes",
+        outcomes_file.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(output.stdout.is_empty());
+
+    // None of the rows were committed, so they have no outcomes
+    assert!(!outcomes_file.exists());
+
+    let input_file = PathBuf::from("./resources/test-examples/inputs/transactions_1.csv");
+
+    let output = run(&[input_file.to_str().unwrap(), "--require-trailer"]);
+    assert_eq!(output.status.code(), Some(5));
+
+    // Same rows without a trailer still match the manifest, and complete with their rejections
+    let output = run(&[
+        input_file.to_str().unwrap(),
+        "--control",
+        manifest_file.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!output.stdout.is_empty());
+
+    // A trailer that can't be parsed refuses the batch, rather than being rejected like a row
+    let bad_trailer_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("bad_trailer.csv");
+    fs::write(
+        &bad_trailer_file,
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,1.0\n\
+         trailer,2,1O.0,0\n",
+    )
+    .unwrap();
+
+    for args in [&[][..], &["--require-trailer"]] {
+        let output = run(&[
+            &[
+                bad_trailer_file.to_str().unwrap(),
+                "--outcomes",
+                outcomes_file.to_str().unwrap(),
+            ],
+            args,
+        ]
+        .concat());
+        assert_eq!(output.status.code(), Some(5));
+        assert!(output.stdout.is_empty());
+        assert!(!outcomes_file.exists());
+    }
+}
+
+#[test]
+fn batch_groups() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs-batch/transactions_1.csv");
+    let expected_file = PathBuf::from("./resources/test-examples/expected-batch/accounts_1.csv");
+
+    run_and_compare(&[input_file.to_str().unwrap()], &expected_file);
+
+    // Identical re-deliveries within a batch are skipped, but a reused ID still rolls it back
+    let input_file = PathBuf::from("./resources/test-examples/inputs-batch/transactions_2.csv");
+    let expected_file = PathBuf::from("./resources/test-examples/expected-batch/accounts_2.csv");
+
+    let output = run_and_compare(&[input_file.to_str().unwrap()], &expected_file);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn camt053_input() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs-camt053/statement_1.xml");
+    let expected_file = PathBuf::from("./resources/test-examples/expected-camt053/accounts_1.csv");
+
+    let output = run_and_compare(&[input_file.to_str().unwrap()], &expected_file);
+
+    // The entry with a non-numeric client reference is rejected
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn binary_input() {
+    let input_file = PathBuf::from("./resources/test-examples/inputs/transactions_1.csv");
+    let expected_file = PathBuf::from("./resources/test-examples/expected/accounts_1.csv");
+    let binary_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("transactions_1.tpeb");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "convert",
+            input_file.to_str().unwrap(),
+            binary_file.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+    assert!(output.status.success());
+
+    run_and_compare(&[binary_file.to_str().unwrap()], &expected_file);
+
+    // Rows the format can't hold all of are rejected, rather than losing their timestamp or batch
+    let input_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("unencodable.csv");
+    let rejects_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("unencodable_rejects.csv");
+    fs::write(
+        &input_file,
+        "type,client,tx,amount,timestamp,batch\n\
+         deposit,1,1,1.0,,\n\
+         deposit,1,2,1.0,1700000000,\n\
+         deposit,1,3,1.0,,payroll-1\n",
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "convert",
+            input_file.to_str().unwrap(),
+            binary_file.to_str().unwrap(),
+            "--rejects",
+            rejects_file.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    let rejects = fs::read_to_string(&rejects_file).unwrap();
+    assert_eq!(rejects.matches("unencodable_field").count(), 2);
+}
+
+#[test]
+fn parallel_parsing() {
+    let input_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("parallel.csv");
+
+    // Enough records for several chunks, with disputes and bad rows that depend on input order
+    let mut input = String::from("type,client,tx,amount\n");
+
+    for id in 1..=5000 {
+        let client = id % 13;
+
+        let row = match id % 10 {
+            0 => format!("withdrawal,{client},{id},7.5\n"),
+            1 => format!("dispute,{client},{},\n", id - 1),
+            2 => format!("resolve,{client},{},\n", id - 2),
+            3 => format!("deposit,{client},{id},-1\n"),
+            4 => format!("deposit,x,{id},1\n"),
+            _ => format!("deposit,{client},{id},{}.25\n", id % 7),
+        };
+
+        input.push_str(&row);
+    }
+
+    fs::write(&input_file, input).unwrap();
+
+    let run = |threads: &str| {
+        let rejects_file =
+            PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("parallel_{threads}.csv"));
+
+        let output = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                input_file.to_str().unwrap(),
+                "--parse-threads",
+                threads,
+                "--rejects",
+                rejects_file.to_str().unwrap(),
+            ])
+            .output()
+            .unwrap();
+
+        let mut reports = parse_reports(&String::from_utf8(output.stdout).unwrap());
+        reports.sort();
+
+        (
+            output.status.code(),
+            reports,
+            fs::read_to_string(rejects_file).unwrap(),
+        )
+    };
+
+    let sequential = run("1");
+    let parallel = run("4");
+
+    assert_eq!(sequential.0, Some(2));
+    assert!(!sequential.1.is_empty());
+    assert_eq!(parallel, sequential);
+}
+
+#[test]
+fn report_output() {
+    let output_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("accounts.csv");
+    let input_file = "./resources/test-examples/inputs/transactions_1.csv";
+
+    let stdout = Command::new("cargo")
+        .args(["run", "--", input_file])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(stdout.stdout).unwrap();
+
+    // Sorted by client, without a trailing blank line
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked\n\
+         1,1.0000,0.0000,1.0000,false\n\
+         2,2.0000,0.0000,2.0000,false\n"
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            input_file,
+            "--output",
+            output_file.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read_to_string(output_file).unwrap(), stdout);
+}
+
+#[test]
+fn output_formats() {
+    let input_file = "./resources/test-examples/inputs/transactions_1.csv";
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--", input_file])
+            .args(args)
+            .output()
+            .unwrap();
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(
+        run(&["--output-format", "json"]),
+        "[\n\
+         {\"client\":\"1\",\"available\":\"1.0000\",\"held\":\"0.0000\",\"total\":\"1.0000\",\"locked\":false},\n\
+         {\"client\":\"2\",\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false}\n\
+         ]\n"
+    );
+
+    assert_eq!(
+        run(&["--output-format", "jsonl", "--json-numbers", "number"]),
+        "{\"client\":1,\"available\":1.0000,\"held\":0.0000,\"total\":1.0000,\"locked\":false}\n\
+         {\"client\":2,\"available\":2.0000,\"held\":0.0000,\"total\":2.0000,\"locked\":false}\n"
+    );
+
+    assert_eq!(
+        run(&["--output-format", "markdown"]),
+        "| client | available | held | total | locked |\n\
+         | ------ | --------- | ---- | ----- | ------ |\n\
+         | 1 | 1.0000 | 0.0000 | 1.0000 | false |\n\
+         | 2 | 2.0000 | 0.0000 | 2.0000 | false |\n"
+    );
+}
+
+#[test]
+fn outcomes_file() {
+    let outcomes_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("outcomes.csv");
+
+    let mut child = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-",
+            "--outcomes",
+            outcomes_file.to_str().unwrap(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5\n\
+                 withdrawal,1,2,10\n\
+                 deposit,1,1,5\n\
+                 deposit,x,3,1\n\
+                 dispute,1,1,\n";
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+
+    assert_eq!(
+        fs::read_to_string(outcomes_file).unwrap(),
+        "line,ledger_index,tx,client,status,reason,available,held,total,locked\n\
+         2,0,1,1,accepted,,5.0000,0.0000,5.0000,false\n\
+         3,1,2,1,rejected,invalid_withdrawal,5.0000,0.0000,5.0000,false\n\
+         4,,1,1,skipped,duplicate,5.0000,0.0000,5.0000,false\n\
+         5,,,,rejected,malformed_record,,,,\n\
+         6,2,1,1,accepted,,0.0000,5.0000,5.0000,false\n"
+    );
+}
+
+#[test]
+fn client_statement() {
+    let mut child = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "statement",
+            "-",
+            "--client",
+            "1",
+            "--from-index",
+            "1",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let input = "type,client,tx,amount,timestamp\n\
+                 deposit,1,1,10,100\n\
+                 deposit,2,2,5,110\n\
+                 withdrawal,1,3,20,120\n\
+                 withdrawal,1,4,3,130\n\
+                 dispute,1,1,,140\n";
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "row,ledger_index,tx,type,amount,timestamp,status,available,held,total\n\
+         opening,,,,,,,10.0000,0.0000,10.0000\n\
+         entry,2,3,withdrawal,20.0000,120,rejected,10.0000,0.0000,10.0000\n\
+         entry,3,4,withdrawal,3.0000,130,accepted,7.0000,0.0000,7.0000\n\
+         entry,4,1,dispute,,140,accepted,-3.0000,10.0000,7.0000\n\
+         closing,,,,,,,-3.0000,10.0000,7.0000\n"
+    );
+}
+
+#[test]
+fn summary_file() {
+    let summary_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("summary.json");
+
+    let mut child = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-",
+            "--summary",
+            summary_file.to_str().unwrap(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,2,2,5\n\
+                 deposit,2,2,5\n\
+                 withdrawal,1,3,20\n\
+                 deposit,x,4,1\n\
+                 dispute,1,1,\n";
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    println!("{}", String::from_utf8(output.stderr).unwrap());
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(summary_file).unwrap()).unwrap();
+
+    assert_eq!(summary["rows_read"], 6);
+    assert_eq!(summary["duplicates_skipped"], 1);
+    assert_eq!(
+        summary["rejected"],
+        serde_json::json!({"deserialize": 1, "parse": 0, "apply": 1})
+    );
+    assert_eq!(
+        summary["transactions"]["deposit"],
+        serde_json::json!({"accepted": 2, "rejected": 0, "amount": "15.0000"})
+    );
+    assert_eq!(
+        summary["disputes"],
+        serde_json::json!({"opened": 1, "resolved": 0, "charged_back": 0})
+    );
+    assert_eq!(summary["accounts"], 2);
+    assert_eq!(summary["locked_accounts"], 0);
+    assert_eq!(summary["total_held"], "10.0000");
+    assert_eq!(summary["largest_balances"][0]["client"], "1");
+}
+
+#[test]
+fn reconcile_accounts() {
+    let input_dir = PathBuf::from("./resources/test-examples/inputs");
+    let expected_dir = PathBuf::from("./resources/test-examples/expected");
+
+    let reconcile = |input: &PathBuf, expected: &PathBuf| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "reconcile",
+                input.to_str().unwrap(),
+                "--expected",
+                expected.to_str().unwrap(),
+            ])
+            .output()
+            .unwrap()
+    };
+
+    let files_to_test = fs::read_dir(input_dir.clone()).unwrap().count();
+
+    for idx in 1..=files_to_test {
+        let input_file = input_dir.join(format!("transactions_{idx}.csv"));
+        let expected_file = expected_dir.join(format!("accounts_{idx}.csv"));
+
+        let output = reconcile(&input_file, &expected_file);
+
+        assert_ne!(output.status.code(), Some(6), "{input_file:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "client,break,field,expected,actual\n"
+        );
+    }
+
+    let expected_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("expected.csv");
+
+    fs::write(
+        &expected_file,
+        "client,available,held,total,locked\n\
+         1,1.0,0,1,false\n\
+         3,0,0,0,false\n\
+         2,2.5,0,2.0000,true\n",
+    )
+    .unwrap();
+
+    let output = reconcile(&input_dir.join("transactions_1.csv"), &expected_file);
+
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,break,field,expected,actual\n\
+         2,mismatch,available,2.5,2.0000\n\
+         2,mismatch,locked,true,false\n\
+         3,missing_client,,,\n"
+    );
+}
+
+#[test]
+fn opening_balances() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+
+    let day_1 = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,5.0\n\
+                 withdrawal,2,3,1.5\n\
+                 dispute,2,2,\n";
+    let day_2 = "type,client,tx,amount\n\
+                 deposit,1,4,2.5\n\
+                 withdrawal,1,5,3.0\n\
+                 dispute,1,4,\n\
+                 deposit,3,6,1.0\n";
+
+    let run = |input: &str, opening: Option<&PathBuf>| {
+        let input_file = tmp_dir.join("opening_input.csv");
+        fs::write(&input_file, input).unwrap();
+
+        let mut args = vec![
+            "run".to_string(),
+            "--".to_string(),
+            input_file.to_str().unwrap().to_string(),
+        ];
+
+        if let Some(opening) = opening {
+            args.push("--opening-balances".to_string());
+            args.push(opening.to_str().unwrap().to_string());
+        }
+
+        Command::new("cargo").args(args).output().unwrap()
+    };
+
+    let first = run(day_1, None);
+    assert_eq!(first.status.code(), Some(0));
+
+    let opening_file = tmp_dir.join("opening_balances.csv");
+    fs::write(&opening_file, &first.stdout).unwrap();
+
+    let second = run(day_2, Some(&opening_file));
+    assert_eq!(second.status.code(), Some(0));
+
+    // Carrying the balances forward gives the same accounts as one run over both days
+    let both_days = format!("{day_1}{}", day_2.split_once('\n').unwrap().1);
+    let single = run(&both_days, None);
+
+    assert_same_reports(&second.stdout, &single.stdout);
+
+    // The held funds of client 2 came from the opening balance, so they can't be resolved
+    let resolved = run("type,client,tx,amount\nresolve,2,2,\n", Some(&opening_file));
+
+    assert_eq!(resolved.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(resolved.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,10.0000,0.0000,10.0000,false\n\
+         2,-1.5000,5.0000,3.5000,false\n"
+    );
+
+    fs::write(
+        &opening_file,
+        "client,available,held,total,locked\n\
+         1,10.0,1.0,10.0,false\n",
+    )
+    .unwrap();
+
+    let mismatch = run(day_2, Some(&opening_file));
+
+    assert_eq!(mismatch.status.code(), Some(1));
+    assert!(String::from_utf8(mismatch.stderr)
+        .unwrap()
+        .contains("Opening balance for client 1 has a total of 10.0000"));
+}
+
+#[test]
+fn resume_from_checkpoint() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let checkpoint_file = tmp_dir.join("checkpoint.json");
+
+    let day_1 = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,5.0\n\
+                 withdrawal,2,3,9.0\n\
+                 deposit,1,4,1.0\n";
+    let day_2 = "type,client,tx,amount\n\
+                 dispute,1,1,\n\
+                 deposit,1,4,1.0\n\
+                 dispute,2,2,\n\
+                 chargeback,2,2,\n\
+                 withdrawal,1,5,0.5\n\
+                 dispute,2,3,\n";
+
+    let run = |name: &str, input: &str, extra: &[&str]| {
+        let input_file = tmp_dir.join(name);
+        fs::write(&input_file, input).unwrap();
+
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                input_file.to_str().unwrap(),
+                "--dispute-window-txs",
+                "5",
+            ])
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let first = run(
+        "checkpoint_day_1.csv",
+        day_1,
+        &["--checkpoint-out", checkpoint_file.to_str().unwrap()],
+    );
+    assert_eq!(first.status.code(), Some(2));
+
+    let summary_file = tmp_dir.join("checkpoint_summary.json");
+    let second = run(
+        "checkpoint_day_2.csv",
+        day_2,
+        &[
+            "--resume-from",
+            checkpoint_file.to_str().unwrap(),
+            "--summary",
+            summary_file.to_str().unwrap(),
+        ],
+    );
+
+    let both_days = format!("{day_1}{}", day_2.split_once('\n').unwrap().1);
+    let single = run("checkpoint_both_days.csv", &both_days, &[]);
+
+    assert_eq!(second.status.code(), single.status.code());
+    assert_eq!(
+        String::from_utf8(second.stdout).unwrap(),
+        String::from_utf8(single.stdout).unwrap()
+    );
+
+    // Only the second day's rows are counted, but the accounts include both days
+    let summary: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(summary_file).unwrap()).unwrap();
+
+    assert_eq!(summary["rows_read"], 6);
+    assert_eq!(summary["duplicates_skipped"], 1);
+    assert_eq!(
+        summary["transactions"],
+        serde_json::json!({
+            "chargeback": { "accepted": 1, "rejected": 0 },
+            "dispute": { "accepted": 2, "rejected": 1 },
+            "withdrawal": { "accepted": 1, "rejected": 0, "amount": "0.5000" },
+        })
+    );
+    assert_eq!(summary["disputes"]["opened"], 2);
+    assert_eq!(summary["accounts"], 2);
+
+    // Resuming with a different policy would give different results, so it isn't allowed
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            tmp_dir.join("checkpoint_day_2.csv").to_str().unwrap(),
+            "--resume-from",
+            checkpoint_file.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Checkpoint was taken with a different account policy"));
+}
+
+#[test]
+fn resume_from_checkpoint_with_out_of_order_timestamps() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let checkpoint_file = tmp_dir.join("timestamped_checkpoint.json");
+
+    // The first part ends with the latest timestamp, past client 1's dispute window,
+    // but the charge back in the second part arrives well within it
+    let part_1 = "type,client,tx,amount,timestamp\n\
+                  deposit,1,1,10.0,1000\n\
+                  dispute,1,1,,1010\n\
+                  deposit,2,2,5.0,1200\n";
+    let part_2 = "type,client,tx,amount,timestamp\n\
+                  chargeback,1,1,,1020\n\
+                  deposit,2,3,1.0,1030\n";
+
+    let run = |name: &str, input: &str, extra: &[&str]| {
+        let input_file = tmp_dir.join(name);
+        fs::write(&input_file, input).unwrap();
+
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                input_file.to_str().unwrap(),
+                "--dispute-window-secs",
+                "100",
+            ])
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let first = run(
+        "timestamped_part_1.csv",
+        part_1,
+        &["--checkpoint-out", checkpoint_file.to_str().unwrap()],
+    );
+    assert_eq!(first.status.code(), Some(0));
+
+    // The report still closes disputes whose window has ended, like a run without a checkpoint
+    assert!(String::from_utf8(first.stdout)
+        .unwrap()
+        .contains("1,10.0000,0.0000,10.0000,false"));
+
+    let second = run(
+        "timestamped_part_2.csv",
+        part_2,
+        &["--resume-from", checkpoint_file.to_str().unwrap()],
+    );
+
+    let both_parts = format!("{part_1}{}", part_2.split_once('\n').unwrap().1);
+    let single = run("timestamped_both_parts.csv", &both_parts, &[]);
+
+    assert_eq!(second.status.code(), single.status.code());
+    assert_eq!(
+        String::from_utf8(second.stdout).unwrap(),
+        String::from_utf8(single.stdout).unwrap()
+    );
+
+    // The charge back was applied, rather than refused for a dispute closed by the first part
+    assert_eq!(second.status.code(), Some(0));
+}
+
+/// Reads a report sent by the server, which ends with an empty line
+fn read_server_report(reader: &mut impl BufRead) -> String {
+    let mut report = String::new();
+
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        if line.trim().is_empty() {
+            return report;
+        }
+
+        report.push_str(&line);
+    }
+}
+
+/// Starts the server on any free port, returning it along with the address it's listening on
+fn spawn_server(args: &[&str]) -> (Child, String) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_toy-payments-engine"))
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut listening = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut listening)
+        .unwrap();
+
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on ")
+        .unwrap()
+        .to_string();
+
+    (server, addr)
+}
+
+#[test]
+fn serve_connections() {
+    let (mut server, addr) = spawn_server(&[]);
+
+    // Every client deposits from its own connection, then waits for a report,
+    // so each connection's rows have been applied once it's answered
+    let connections: Vec<_> = (1..=20u32)
+        .map(|client| {
+            let addr = addr.clone();
+
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+
+                let mut rows = "type, client, tx, amount\n".to_string();
+                for n in 0..10 {
+                    rows.push_str(&format!("deposit, {client}, {}, 1.5\n", client * 100 + n));
+                }
+                rows.push_str("REPORT\n");
+
+                stream.write_all(rows.as_bytes()).unwrap();
+                read_server_report(&mut BufReader::new(stream))
+            })
+        })
+        .collect();
+
+    for connection in connections {
+        connection.join().unwrap();
+    }
+
+    // Rows from any connection can refer to transactions sent over another
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    stream
+        .write_all(
+            b"type,client,tx,amount\n\
+              dispute,1,100,\n\
+              withdrawal,2,1,100.0\n\
+              REPORT\n",
+        )
+        .unwrap();
+
+    let report = read_server_report(&mut BufReader::new(stream));
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let reports = parse_reports(&report);
+
+    assert_eq!(reports.len(), 20);
+    assert_eq!(
+        (
+            reports[0].available.as_str(),
+            reports[0].held.as_str(),
+            reports[0].total.as_str()
+        ),
+        ("13.5000", "1.5000", "15.0000")
+    );
+    assert!(reports[1..]
+        .iter()
+        .all(|report| report.total == "15.0000" && report.held == "0.0000"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn serve_stops_when_engine_fails() {
+    // Writing outcomes always fails, so the first row stops the engine
+    let (mut server, addr) = spawn_server(&["--outcomes", "/dev/full"]);
+
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    stream
+        .write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\n")
+        .unwrap();
+
+    // The whole server stops, with the status of the error rather than success
+    let status = server.wait().unwrap();
+    assert_eq!(status.code(), Some(7));
+
+    assert!(TcpStream::connect(&addr).is_err());
+}